@@ -1,45 +1,259 @@
-use crate::block::{Alignment, Config, GlobalConfig};
-use crate::text::ComputedText;
+mod tray;
+
+use crate::block::{Alignment, Block, Config, GlobalConfig, Tasks, Update};
+use crate::event::Event;
+use crate::text::{color::Color, ComputedText, Text};
+use async_std::{sync::Sender, task};
+use std::{error::Error, sync::Arc, thread};
+use tray::Tray;
 use xcb::base::Connection;
 use xcb_util::ewmh;
 
-pub struct Bar {
-    conn: ewmh::Connection,
-    window_id: u32,
-    screen_idx: usize,
+/// The part of the root window shown on a RandR output.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Output {
+    x: i16,
+    y: i16,
+    width: u16,
+    height: u16,
+}
+
+/// What a piece of laid out text is drawn for.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Owner {
+    /// The block at this index of its section.
+    Block(usize),
+    Separator,
+    /// Space where the tray icons go.
+    Tray,
+    /// The error that stopped the config from being reloaded.
+    Error,
+}
+
+/// The bar's window on one output.
+struct Window {
+    id: u32,
     surface: cairo::XCBSurface,
     width: u16,
     height: u16,
+    contents_cache: Vec<(Alignment, Owner, ComputedText)>,
+}
+
+pub struct Bar {
+    conn: Arc<ewmh::Connection>,
+    screen_idx: usize,
+    outputs: Vec<Output>,
+    windows: Vec<Window>,
     pub contents: Config,
     global_config: GlobalConfig,
-    contents_cache: Vec<ComputedText>,
+    tray: Option<Tray>,
+    /// Shown at the start of the bar until the config is fixed.
+    error: Option<String>,
+    /// Whether the windows are unmapped.
+    hidden: bool,
 }
 
 impl Bar {
-    pub fn new(
-        global_config: GlobalConfig,
-        config: Config,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(global_config: GlobalConfig, config: Config) -> Result<Self, Box<dyn Error>> {
         let (conn, screen_idx) = Connection::connect(None)?;
-        let screen = conn
+        let conn = ewmh::Connection::connect(conn).map_err(|(e, _)| e)?;
+        let mut bar = Bar {
+            conn: Arc::new(conn),
+            screen_idx: screen_idx as usize,
+            outputs: vec![],
+            windows: vec![],
+            contents: config,
+            global_config,
+            tray: None,
+            error: None,
+            hidden: false,
+        };
+        if bar.has_randr() {
+            let root = bar.screen()?.root();
+            xcb::randr::select_input(
+                &bar.conn,
+                root,
+                xcb::randr::NOTIFY_MASK_SCREEN_CHANGE as u16,
+            );
+        }
+        bar.update_tray()?;
+        bar.update_outputs()?;
+        Ok(bar)
+    }
+
+    /// Starts or stops the tray if the config asks for it, or moves it to
+    /// its configured section.
+    fn update_tray(&mut self) -> Result<(), Box<dyn Error>> {
+        match (self.global_config.tray, &mut self.tray) {
+            (Some(alignment), Some(tray)) => tray.alignment = alignment,
+            (Some(alignment), None) => {
+                match Tray::new(&self.conn, &self.screen()?, self.screen_idx, alignment) {
+                    Ok(tray) => self.tray = Some(tray),
+                    Err(e) => eprintln!("Warning: not showing the system tray: {}", e),
+                }
+            }
+            (None, Some(tray)) => {
+                tray.release(&self.conn);
+                self.tray = None;
+            }
+            (None, None) => (),
+        }
+        Ok(())
+    }
+
+    /// Switches to a reloaded config, recreating the windows in case their
+    /// geometry changed. Returns the old blocks, whose tasks may be kept for
+    /// the new ones.
+    pub fn reload(&mut self, global_config: GlobalConfig, config: Config) -> Config {
+        let old = std::mem::replace(&mut self.contents, config);
+        self.global_config = global_config;
+        self.error = None;
+        self.outputs.clear();
+        if let Err(e) = self.update_tray().and_then(|_| self.update_outputs()) {
+            eprintln!("Failed to recreate the bars: {}", e);
+        }
+        old
+    }
+
+    /// Shows `error` at the start of the bar until the next successful
+    /// reload.
+    pub fn show_error(&mut self, error: String) {
+        self.error = Some(error);
+        self.render_contents();
+    }
+
+    fn screen(&self) -> Result<xcb::Screen<'_>, &'static str> {
+        self.conn
             .get_setup()
             .roots()
-            .nth(screen_idx as usize)
-            .ok_or("invalid screen_idx")?;
-        let width = screen.width_in_pixels();
-        let height = 22;
+            .nth(self.screen_idx)
+            .ok_or("invalid screen_idx")
+    }
+
+    fn has_randr(&self) -> bool {
+        self.conn
+            .get_extension_data(xcb::randr::id())
+            .is_some_and(|e| e.present())
+    }
+
+    /// Recreates the windows if the connected outputs changed since the last
+    /// call, returning whether they did.
+    ///
+    /// There's one window per output, and multi monitor blocks get one
+    /// output per window.
+    pub fn update_outputs(&mut self) -> Result<bool, Box<dyn Error>> {
+        let outputs = self.query_outputs()?;
+        if outputs == self.outputs {
+            return Ok(false);
+        }
+        if let Some(tray) = &self.tray {
+            tray.unembed(&self.conn);
+        }
+        for window in self.windows.drain(..) {
+            xcb::destroy_window(&self.conn, window.id);
+        }
+        self.windows = outputs
+            .iter()
+            .enumerate()
+            .map(|(monitor, output)| self.create_window(monitor, output))
+            .collect::<Result<_, _>>()?;
+        // The tray is always on the first monitor.
+        if let (Some(tray), Some(window)) = (&self.tray, self.windows.first()) {
+            tray.reembed(&self.conn, window.id);
+        }
+        self.outputs = outputs;
+        let n_monitor = self.windows.len();
+        self.contents
+            .values_mut()
+            .flatten()
+            .for_each(|b| b.set_monitors(n_monitor));
+        self.conn.flush();
+        Ok(true)
+    }
+
+    /// The connected outputs, ordered left to right, or the whole screen if
+    /// RandR isn't available.
+    fn query_outputs(&self) -> Result<Vec<Output>, Box<dyn Error>> {
+        let screen = self.screen()?;
+        let whole_screen = Output {
+            x: 0,
+            y: 0,
+            width: screen.width_in_pixels(),
+            height: screen.height_in_pixels(),
+        };
+        if !self.has_randr() {
+            return Ok(vec![whole_screen]);
+        }
+        let resources =
+            xcb::randr::get_screen_resources_current(&self.conn, screen.root()).get_reply()?;
+        let timestamp = resources.config_timestamp();
+        let mut outputs = vec![];
+        for &output in resources.outputs() {
+            let info = xcb::randr::get_output_info(&self.conn, output, timestamp).get_reply()?;
+            if u32::from(info.connection()) != xcb::randr::CONNECTION_CONNECTED
+                || info.crtc() == xcb::NONE
+            {
+                continue;
+            }
+            let crtc = xcb::randr::get_crtc_info(&self.conn, info.crtc(), timestamp).get_reply()?;
+            let output = Output {
+                x: crtc.x(),
+                y: crtc.y(),
+                width: crtc.width(),
+                height: crtc.height(),
+            };
+            // Mirrored outputs would get two bars on top of each other.
+            if !outputs.contains(&output) {
+                outputs.push(output);
+            }
+        }
+        outputs.sort_by_key(|o| (o.x, o.y));
+        if outputs.is_empty() {
+            outputs.push(whole_screen);
+        }
+        Ok(outputs)
+    }
+
+    fn create_window(&self, monitor: usize, output: &Output) -> Result<Window, Box<dyn Error>> {
+        let conn = &self.conn;
+        let screen = self.screen()?;
+        let global_config = &self.global_config;
+        let geometry = global_config
+            .bars_geometries
+            .get(monitor)
+            .copied()
+            .unwrap_or(global_config.base_geometry);
+        let width = geometry
+            .width
+            .unwrap_or_else(|| output.width.saturating_sub(geometry.x));
+        let height = geometry.height.unwrap_or(22);
+        let x = output.x.saturating_add(geometry.x as i16);
+        let y = if global_config.bottom {
+            output.y.saturating_add(
+                output
+                    .height
+                    .saturating_sub(height)
+                    .saturating_sub(geometry.y) as i16,
+            )
+        } else {
+            output.y.saturating_add(geometry.y as i16)
+        };
+        let background = global_config.background.unwrap_or_else(Color::black);
         let id = conn.generate_id();
         let values = [
-            (xcb::CW_BACK_PIXEL, screen.black_pixel()),
-            (xcb::CW_EVENT_MASK, xcb::EVENT_MASK_EXPOSURE),
+            (xcb::CW_BACK_PIXEL, background.to_pixel()),
+            (
+                xcb::CW_EVENT_MASK,
+                xcb::EVENT_MASK_EXPOSURE | xcb::EVENT_MASK_BUTTON_PRESS,
+            ),
         ];
         xcb::create_window(
-            &conn,
+            conn,
             xcb::COPY_FROM_PARENT as u8,
             id,
             screen.root(),
-            0,
-            0,
+            x,
+            y,
             width,
             height,
             0,
@@ -48,80 +262,428 @@ impl Bar {
             &values,
         );
         let surface =
-            cairo_surface_for_xcb_window(&conn, &screen, id, i32::from(width), i32::from(height))
+            cairo_surface_for_xcb_window(conn, &screen, id, i32::from(width), i32::from(height))
                 .map_err(|e| format!("Couldn't create cairo surface: {}", e))?;
-        let ewmh_conn = ewmh::Connection::connect(conn).map_err(|(e, _)| e)?;
-        ewmh::set_wm_window_type(&ewmh_conn, id, &[ewmh_conn.WM_WINDOW_TYPE_DOCK()]);
-        let strut_partial = ewmh::StrutPartial {
-            left: 0,
-            right: 0,
-            top: u32::from(height),
-            bottom: 0,
-            left_start_y: 0,
-            left_end_y: 0,
-            right_start_y: 0,
-            right_end_y: 0,
-            top_start_x: 0,
-            top_end_x: 0,
-            bottom_start_x: 0,
-            bottom_end_x: 0,
+        ewmh::set_wm_window_type(conn, id, &[conn.WM_WINDOW_TYPE_DOCK()]);
+        let name = global_config.name.as_deref().unwrap_or("lemonjuice");
+        xcb::change_property(
+            conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            id,
+            xcb::ATOM_WM_NAME,
+            xcb::ATOM_STRING,
+            8,
+            name.as_bytes(),
+        );
+        ewmh::set_wm_name(conn, id, name);
+        // Struts are measured from the edges of the whole screen, not the output.
+        let start_x = x.max(0) as u32;
+        let end_x = start_x + u32::from(width).saturating_sub(1);
+        let strut_partial = if global_config.bottom {
+            ewmh::StrutPartial {
+                left: 0,
+                right: 0,
+                top: 0,
+                bottom: (i32::from(screen.height_in_pixels()) - i32::from(y)).max(0) as u32,
+                left_start_y: 0,
+                left_end_y: 0,
+                right_start_y: 0,
+                right_end_y: 0,
+                top_start_x: 0,
+                top_end_x: 0,
+                bottom_start_x: start_x,
+                bottom_end_x: end_x,
+            }
+        } else {
+            ewmh::StrutPartial {
+                left: 0,
+                right: 0,
+                top: (i32::from(y) + i32::from(height)).max(0) as u32,
+                bottom: 0,
+                left_start_y: 0,
+                left_end_y: 0,
+                right_start_y: 0,
+                right_end_y: 0,
+                top_start_x: start_x,
+                top_end_x: end_x,
+                bottom_start_x: 0,
+                bottom_end_x: 0,
+            }
         };
-        ewmh::set_wm_strut_partial(&ewmh_conn, id, strut_partial);
-        xcb::map_window(&ewmh_conn, id);
-        ewmh_conn.flush();
-        Ok(Bar {
-            conn: ewmh_conn,
-            window_id: id,
-            screen_idx: screen_idx as usize,
+        ewmh::set_wm_strut_partial(conn, id, strut_partial);
+        if !self.hidden {
+            xcb::map_window(conn, id);
+        }
+        Ok(Window {
+            id,
             surface,
             width,
             height,
-            contents: config,
-            global_config: global_config,
             contents_cache: vec![],
         })
     }
 
-    pub fn render(&self, text: crate::text::Text) {
-        text.compute(&self.surface)
-            .unwrap()
-            .render(&self.surface)
-            .unwrap();
+    /// Forwards the windows' X events to `sender` from a background thread.
+    pub fn listen(&self, sender: Sender<Event>) {
+        let conn = Arc::clone(&self.conn);
+        let screen_change = conn
+            .get_extension_data(xcb::randr::id())
+            .filter(|e| e.present())
+            .map(|e| e.first_event() + xcb::randr::SCREEN_CHANGE_NOTIFY);
+        thread::spawn(move || {
+            while let Some(event) = conn.wait_for_event() {
+                let event = match event.response_type() & !0x80 {
+                    xcb::EXPOSE => Event::Expose,
+                    xcb::BUTTON_PRESS => {
+                        let press: &xcb::ButtonPressEvent = unsafe { xcb::cast_event(&event) };
+                        Event::Click {
+                            window: press.event(),
+                            x: press.event_x(),
+                            button: press.detail(),
+                        }
+                    }
+                    xcb::CLIENT_MESSAGE => {
+                        let message: &xcb::ClientMessageEvent = unsafe { xcb::cast_event(&event) };
+                        let mut data = [0; 5];
+                        data.copy_from_slice(message.data().data32());
+                        Event::ClientMessage {
+                            message_type: message.type_(),
+                            data,
+                        }
+                    }
+                    xcb::DESTROY_NOTIFY => {
+                        let destroy: &xcb::DestroyNotifyEvent = unsafe { xcb::cast_event(&event) };
+                        Event::WindowGone(destroy.window())
+                    }
+                    xcb::REPARENT_NOTIFY => {
+                        let reparent: &xcb::ReparentNotifyEvent =
+                            unsafe { xcb::cast_event(&event) };
+                        Event::Reparented {
+                            window: reparent.window(),
+                            parent: reparent.parent(),
+                        }
+                    }
+                    xcb::SELECTION_CLEAR => {
+                        let clear: &xcb::SelectionClearEvent = unsafe { xcb::cast_event(&event) };
+                        Event::SelectionLost(clear.selection())
+                    }
+                    r if Some(r) == screen_change => Event::OutputsChanged,
+                    _ => continue,
+                };
+                task::block_on(sender.send(event));
+            }
+        });
+    }
+
+    /// Runs the action of the block drawn under `x` in `window` for the
+    /// pressed `button`.
+    pub fn click(&self, window: u32, x: i16, button: u8) {
+        let monitor = match self.windows.iter().position(|w| w.id == window) {
+            Some(monitor) => monitor,
+            None => return,
+        };
+        let x = f64::from(x);
+        let clicked = self.windows[monitor]
+            .contents_cache
+            .iter()
+            .find(|(_, _, t)| t.x <= x && x < t.x + t.width);
+        if let Some((alignment, Owner::Block(index), text)) = clicked {
+            if let Some(block) = self.contents.get(alignment).and_then(|b| b.get(*index)) {
+                let action = text
+                    .actions
+                    .get(usize::from(button).wrapping_sub(1))
+                    .and_then(|a| a.as_deref())
+                    .or_else(|| block.action(button));
+                if let Some(action) = action {
+                    block.run_action(action, button, *index, monitor);
+                }
+            }
+        }
+    }
+
+    /// Handles a client message, returning whether the bar needs a redraw.
+    pub fn client_message(&mut self, message_type: u32, data: [u32; 5]) -> bool {
+        match (&mut self.tray, self.windows.first()) {
+            (Some(tray), Some(window)) => {
+                tray.client_message(&self.conn, window.id, message_type, data)
+            }
+            _ => false,
+        }
+    }
+
+    /// Forgets about a window that's no longer one of the bar's children,
+    /// returning whether the bar needs a redraw.
+    pub fn window_gone(&mut self, window: u32) -> bool {
+        self.tray.as_mut().is_some_and(|t| t.remove(window))
+    }
+
+    /// Handles a window being reparented, returning whether the bar needs a
+    /// redraw.
+    pub fn reparented(&mut self, window: u32, parent: u32) -> bool {
+        if self.windows.iter().any(|w| w.id == parent) {
+            false
+        } else {
+            self.window_gone(window)
+        }
+    }
+
+    /// Handles another client taking over a selection, returning whether the
+    /// bar needs a redraw.
+    pub fn selection_lost(&mut self, selection: u32) -> bool {
+        match self.tray.take() {
+            Some(mut tray) if tray.is_selection(selection) => {
+                eprintln!("Warning: another system tray took over");
+                tray.release(&self.conn);
+                true
+            }
+            tray => {
+                self.tray = tray;
+                false
+            }
+        }
+    }
+
+    /// The blocks that update when `SIGRTMIN` plus `offset` is received.
+    pub fn signalled_by(&self, offset: u8) -> Vec<(Alignment, usize)> {
+        self.contents
+            .iter()
+            .flat_map(|(&alignment, blocks)| {
+                blocks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, b)| b.signal() == Some(offset))
+                    .map(move |(i, _)| (alignment, i))
+            })
+            .collect()
+    }
+
+    /// Stores a block's new output from one of `tasks`, returning whether
+    /// the bar needs a redraw. Updates from tasks that were stopped since are
+    /// dropped.
+    pub fn apply(&mut self, update: Update, tasks: &Tasks) -> bool {
+        tasks
+            .position(update.task)
+            .and_then(|(alignment, index)| self.contents.get_mut(&alignment)?.get_mut(index))
+            .map(|b| b.set_output(update.monitor, update.output))
+            .unwrap_or(false)
+    }
+
+    /// Replaces the output of the block at `index` of the `alignment`
+    /// section on every monitor, returning whether it changed.
+    pub fn set_output(&mut self, alignment: Alignment, index: usize, text: &str) -> bool {
+        let n_monitor = self.windows.len();
+        let block = match self
+            .contents
+            .get_mut(&alignment)
+            .and_then(|blocks| blocks.get_mut(index))
+        {
+            Some(block) => block,
+            None => return false,
+        };
+        let mut changed = false;
+        for monitor in 0..n_monitor {
+            changed |= block.set_output(monitor, Ok(text.to_string()));
+        }
+        changed
+    }
+
+    /// Unmaps the windows, or maps them back.
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+        for window in &self.windows {
+            if hidden {
+                xcb::unmap_window(&self.conn, window.id);
+            } else {
+                xcb::map_window(&self.conn, window.id);
+            }
+        }
         self.conn.flush();
     }
 
-    pub fn render_contents(&mut self, monitor: usize) {
-        self.contents_cache.clear();
-        if let Some(blocks) = self.contents.get_mut(&Alignment::Right) {
-            let surface = &self.surface;
-            let contents_cache = &mut self.contents_cache;
-            blocks
-                .iter_mut()
-                .map(|b| b.to_text(monitor))
-                .filter_map(|x| x)
-                .map(|t| t.compute(surface))
-                .try_for_each(|maybe_t| maybe_t.map(|t| contents_cache.push(t)))
-                .expect("Failed to render the right side");
-        }
-        if let Some(blocks) = self.contents.get_mut(&Alignment::Left) {
-            let surface = &self.surface;
-            let contents_cache = &mut self.contents_cache;
-            blocks
-                .iter_mut()
-                .map(|b| b.to_text(monitor))
-                .filter_map(|x| x)
-                .map(|t| t.compute(surface))
-                .try_for_each(|maybe_t| maybe_t.map(|t| contents_cache.push(t)))
-                .expect("Failed to render the left side");
+    pub fn render_contents(&mut self) {
+        for (monitor, window) in self.windows.iter_mut().enumerate() {
+            let tray = self.tray.as_ref().filter(|t| monitor == 0 && !t.is_empty());
+            let tray_space = tray.map(|t| (t.alignment, t.width(window.height)));
+            let tray_x = window.render(
+                &self.contents,
+                &self.global_config,
+                monitor,
+                tray_space,
+                self.error.as_deref(),
+            );
+            if let (Some(tray), Some(x)) = (tray, tray_x) {
+                tray.place(&self.conn, x, window.height);
+            }
+        }
+        self.conn.flush();
+    }
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        if let Some(tray) = &mut self.tray {
+            tray.release(&self.conn);
+        }
+        self.conn.flush();
+    }
+}
+
+impl Window {
+    /// Draws the blocks for `monitor`, leaving room for the tray at the end of
+    /// its section and showing `error` first, if given. Returns where the
+    /// tray's space starts.
+    fn render(
+        &mut self,
+        contents: &Config,
+        global_config: &GlobalConfig,
+        monitor: usize,
+        tray: Option<(Alignment, f64)>,
+        error: Option<&str>,
+    ) -> Option<f64> {
+        let context = cairo::Context::new(&self.surface);
+        global_config
+            .background
+            .unwrap_or_else(Color::black)
+            .apply_to_context(&context);
+        context.paint();
+
+        let section = |alignment, short| {
+            let mut section =
+                self.compute_section(contents, global_config, alignment, monitor, short);
+            if let Some((_, width)) = tray.filter(|(a, _)| *a == alignment) {
+                let mut space = Text {
+                    attr: global_config.attributes(),
+                    text: String::new(),
+                    actions: Default::default(),
+                    markup: false,
+                };
+                space.attr.padding = space.attr.padding.with_left(width);
+                let space = space
+                    .compute(&self.surface)
+                    .expect("Failed to make room for the tray");
+                section.push((alignment, Owner::Tray, space));
+            }
+            section
+        };
+        let width = f64::from(self.width);
+        let (mut left, mut middle, mut right) = (
+            section(Alignment::Left, false),
+            section(Alignment::Middle, false),
+            section(Alignment::Right, false),
+        );
+        // Blocks with short texts switch to them when the bar overflows.
+        if section_width(&left) + section_width(&middle) + section_width(&right) > width {
+            left = section(Alignment::Left, true);
+            middle = section(Alignment::Middle, true);
+            right = section(Alignment::Right, true);
+        }
+        if let Some(error) = error {
+            let error = Text {
+                attr: global_config.error_attributes(),
+                text: error.to_string(),
+                actions: Default::default(),
+                markup: false,
+            }
+            .compute(&self.surface)
+            .expect("Failed to render the config error");
+            left.insert(0, (Alignment::Left, Owner::Error, error));
+        }
+
+        // Left blocks have priority, then right blocks and the middle section
+        // gets whatever space is left between them.
+        let left_end = place(&mut left, 0.0, width);
+        let right_width = section_width(&right).min(width - left_end);
+        let right_start = width - right_width;
+        place(&mut right, right_start, right_width);
+        let middle_width = section_width(&middle).min(right_start - left_end);
+        let middle_start = ((width - middle_width) / 2.0)
+            .max(left_end)
+            .min(right_start - middle_width);
+        place(&mut middle, middle_start, middle_width);
+
+        self.contents_cache = left;
+        self.contents_cache.append(&mut middle);
+        self.contents_cache.append(&mut right);
+        // Backgrounds and lines span the whole height of the bar.
+        let height = f64::from(self.height);
+        for (_, _, t) in &mut self.contents_cache {
+            t.y = 0.0;
+            t.height = height;
         }
         self.contents_cache
             .iter()
-            .try_for_each(|t| t.render(&self.surface))
+            .filter(|(_, _, t)| t.width > 0.0)
+            .try_for_each(|(_, _, t)| t.render(&self.surface))
             .expect("Render failed");
-        self.conn.flush();
+        self.contents_cache
+            .iter()
+            .find(|(_, owner, _)| *owner == Owner::Tray)
+            .map(|(_, _, t)| t.x)
+    }
+
+    fn compute_section(
+        &self,
+        contents: &Config,
+        global_config: &GlobalConfig,
+        alignment: Alignment,
+        monitor: usize,
+        short: bool,
+    ) -> Vec<(Alignment, Owner, ComputedText)> {
+        let blocks = match contents.get(&alignment) {
+            Some(blocks) => blocks,
+            None => return vec![],
+        };
+        let mut texts = vec![];
+        let mut previous: Option<&Block> = None;
+        for (i, block) in blocks.iter().enumerate() {
+            // Skipping empty blocks avoids drawing two separators in a row.
+            if block.is_empty(monitor) {
+                continue;
+            }
+            if let (Some(separator), Some(previous)) = (&global_config.separator, previous) {
+                if previous.separator_after(monitor) && block.separator_before() {
+                    texts.push((
+                        Owner::Separator,
+                        Text {
+                            attr: global_config.attributes(),
+                            text: separator.clone(),
+                            actions: Default::default(),
+                            markup: false,
+                        },
+                    ));
+                }
+            }
+            texts.extend(
+                block
+                    .to_texts(monitor, global_config, short)
+                    .into_iter()
+                    .map(|t| (Owner::Block(i), t)),
+            );
+            previous = Some(block);
+        }
+        texts
+            .into_iter()
+            .map(|(owner, t)| t.compute(&self.surface).map(|t| (alignment, owner, t)))
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|e| panic!("Failed to render the {} side: {}", alignment, e))
     }
 }
 
+fn section_width(section: &[(Alignment, Owner, ComputedText)]) -> f64 {
+    section.iter().map(|(_, _, t)| t.width).sum()
+}
+
+/// Lays out `section` left to right starting at `x`, truncating the blocks
+/// that don't fit in `max_width`. Returns where the section ends.
+fn place(section: &mut [(Alignment, Owner, ComputedText)], x: f64, max_width: f64) -> f64 {
+    let end = x + max_width.max(0.0);
+    section.iter_mut().fold(x, |x, (_, _, t)| {
+        t.x = x;
+        t.width = t.width.min(end - x);
+        x + t.width
+    })
+}
+
 fn get_root_visual_type(conn: &xcb::Connection, screen: &xcb::Screen<'_>) -> xcb::Visualtype {
     for root in conn.get_setup().roots() {
         for allowed_depth in root.allowed_depths() {
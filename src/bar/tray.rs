@@ -0,0 +1,217 @@
+use crate::block::Alignment;
+use std::error::Error;
+use xcb_util::ewmh;
+
+const SYSTEM_TRAY_REQUEST_DOCK: u32 = 0;
+const XEMBED_EMBEDDED_NOTIFY: u32 = 0;
+const SYSTEM_TRAY_ORIENTATION_HORZ: u32 = 0;
+
+/// A system tray host, following the freedesktop system tray and XEmbed
+/// specifications.
+pub struct Tray {
+    /// Unmapped window that owns the `_NET_SYSTEM_TRAY_S<n>` selection.
+    owner: u32,
+    root: u32,
+    selection: xcb::Atom,
+    opcode: xcb::Atom,
+    xembed: xcb::Atom,
+    /// Docked icon windows, left to right.
+    icons: Vec<u32>,
+    /// The section the icons are drawn in.
+    pub alignment: Alignment,
+}
+
+fn intern(conn: &xcb::Connection, name: &str) -> Result<xcb::Atom, xcb::GenericError> {
+    Ok(xcb::intern_atom(conn, false, name).get_reply()?.atom())
+}
+
+impl Tray {
+    /// Acquires the tray selection of screen `screen_idx`, announcing it to
+    /// the clients that are waiting for a tray.
+    pub fn new(
+        conn: &ewmh::Connection,
+        screen: &xcb::Screen<'_>,
+        screen_idx: usize,
+        alignment: Alignment,
+    ) -> Result<Self, Box<dyn Error>> {
+        let selection = intern(conn, &format!("_NET_SYSTEM_TRAY_S{}", screen_idx))?;
+        if xcb::get_selection_owner(conn, selection)
+            .get_reply()?
+            .owner()
+            != xcb::NONE
+        {
+            return Err("another system tray is already running".into());
+        }
+        let tray = Tray {
+            owner: conn.generate_id(),
+            root: screen.root(),
+            selection,
+            opcode: intern(conn, "_NET_SYSTEM_TRAY_OPCODE")?,
+            xembed: intern(conn, "_XEMBED")?,
+            icons: vec![],
+            alignment,
+        };
+        xcb::create_window(
+            conn,
+            xcb::COPY_FROM_PARENT as u8,
+            tray.owner,
+            tray.root,
+            -1,
+            -1,
+            1,
+            1,
+            0,
+            xcb::WINDOW_CLASS_INPUT_ONLY as u16,
+            screen.root_visual(),
+            &[],
+        );
+        xcb::change_property(
+            conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            tray.owner,
+            intern(conn, "_NET_SYSTEM_TRAY_ORIENTATION")?,
+            xcb::ATOM_CARDINAL,
+            32,
+            &[SYSTEM_TRAY_ORIENTATION_HORZ],
+        );
+        xcb::set_selection_owner(conn, tray.owner, selection, xcb::CURRENT_TIME);
+        if xcb::get_selection_owner(conn, selection)
+            .get_reply()?
+            .owner()
+            != tray.owner
+        {
+            xcb::destroy_window(conn, tray.owner);
+            return Err("couldn't acquire the system tray selection".into());
+        }
+        let manager = xcb::ClientMessageEvent::new(
+            32,
+            tray.root,
+            intern(conn, "MANAGER")?,
+            xcb::ClientMessageData::from_data32([xcb::CURRENT_TIME, selection, tray.owner, 0, 0]),
+        );
+        xcb::send_event(
+            conn,
+            false,
+            tray.root,
+            xcb::EVENT_MASK_STRUCTURE_NOTIFY,
+            &manager,
+        );
+        Ok(tray)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.icons.is_empty()
+    }
+
+    /// The width all the icons take up, if each is `size` pixels wide.
+    pub fn width(&self, size: u16) -> f64 {
+        f64::from(size) * self.icons.len() as f64
+    }
+
+    /// Handles a client message sent to the tray, docking the icon into
+    /// `embedder` if it's a dock request. Returns whether an icon was added.
+    pub fn client_message(
+        &mut self,
+        conn: &ewmh::Connection,
+        embedder: u32,
+        message_type: xcb::Atom,
+        data: [u32; 5],
+    ) -> bool {
+        if message_type != self.opcode || data[1] != SYSTEM_TRAY_REQUEST_DOCK {
+            return false;
+        }
+        let icon = data[2];
+        if self.icons.contains(&icon) {
+            return false;
+        }
+        // If the bar dies the icons go back to the root window instead of
+        // being destroyed with it.
+        xcb::change_save_set(conn, xcb::SET_MODE_INSERT as u8, icon);
+        xcb::change_window_attributes(
+            conn,
+            icon,
+            &[(xcb::CW_EVENT_MASK, xcb::EVENT_MASK_STRUCTURE_NOTIFY)],
+        );
+        self.icons.push(icon);
+        self.embed(conn, icon, embedder);
+        true
+    }
+
+    fn embed(&self, conn: &ewmh::Connection, icon: u32, embedder: u32) {
+        xcb::reparent_window(conn, icon, embedder, 0, 0);
+        let notify = xcb::ClientMessageEvent::new(
+            32,
+            icon,
+            self.xembed,
+            xcb::ClientMessageData::from_data32([
+                xcb::CURRENT_TIME,
+                XEMBED_EMBEDDED_NOTIFY,
+                0,
+                embedder,
+                0,
+            ]),
+        );
+        xcb::send_event(conn, false, icon, xcb::EVENT_MASK_NO_EVENT, &notify);
+        xcb::map_window(conn, icon);
+    }
+
+    /// Moves every icon into a new `embedder`, for when the bar's windows are
+    /// recreated.
+    pub fn reembed(&self, conn: &ewmh::Connection, embedder: u32) {
+        for &icon in &self.icons {
+            self.embed(conn, icon, embedder);
+        }
+    }
+
+    /// Moves the icons out of the bar's windows so they survive them being
+    /// destroyed.
+    pub fn unembed(&self, conn: &ewmh::Connection) {
+        for &icon in &self.icons {
+            xcb::unmap_window(conn, icon);
+            xcb::reparent_window(conn, icon, self.root, 0, 0);
+        }
+    }
+
+    /// Forgets `window` if it's a docked icon, returning whether it was.
+    pub fn remove(&mut self, window: u32) -> bool {
+        let len = self.icons.len();
+        self.icons.retain(|&icon| icon != window);
+        len != self.icons.len()
+    }
+
+    /// Whether this is the tray's selection, which another tray took over.
+    pub fn is_selection(&self, selection: xcb::Atom) -> bool {
+        self.selection == selection
+    }
+
+    /// Lines the icons up starting at `x`, each as a `size` pixels square.
+    pub fn place(&self, conn: &ewmh::Connection, x: f64, size: u16) {
+        for (i, &icon) in self.icons.iter().enumerate() {
+            xcb::configure_window(
+                conn,
+                icon,
+                &[
+                    (
+                        xcb::CONFIG_WINDOW_X as u16,
+                        (x as u32) + i as u32 * u32::from(size),
+                    ),
+                    (xcb::CONFIG_WINDOW_Y as u16, 0),
+                    (xcb::CONFIG_WINDOW_WIDTH as u16, u32::from(size)),
+                    (xcb::CONFIG_WINDOW_HEIGHT as u16, u32::from(size)),
+                ],
+            );
+        }
+    }
+
+    /// Gives up the selection and hands the icons back to the root window.
+    pub fn release(&mut self, conn: &ewmh::Connection) {
+        self.unembed(conn);
+        self.icons.clear();
+        // If another tray took over the selection it's not ours to give up.
+        let owner = xcb::get_selection_owner(conn, self.selection).get_reply();
+        if owner.is_ok_and(|o| o.owner() == self.owner) {
+            xcb::set_selection_owner(conn, xcb::NONE, self.selection, xcb::CURRENT_TIME);
+        }
+        xcb::destroy_window(conn, self.owner);
+    }
+}
@@ -1,34 +1,130 @@
 mod error;
+mod i3bar;
+mod include;
 mod one_or_more;
+mod section;
 mod stream;
+mod toml_config;
 
-use crate::text::{color::Color, Attributes, Font, Padding, Text};
+use crate::text::{self, color::Color, markup, Attributes, Font, Text};
+pub use error::Diagnostics;
 use error::ParseError;
 use one_or_more::OneOrMore;
+use section::{Entry, Kind, Section};
 use std::{
     collections::HashMap,
-    convert::TryFrom,
-    io, mem,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     str::{self, FromStr},
-    sync::{Arc, RwLock},
+    thread,
     time::Duration,
 };
+pub use stream::{respawn_blocks, spawn_blocks, Failure, Tasks, Update};
+pub use toml_config::convert;
 
 pub type Config = HashMap<Alignment, Vec<Block>>;
 
-pub fn parse(config: &str, monitor: usize) -> Result<(GlobalConfig, Config), ParseError> {
-    let mut blocks = HashMap::<Alignment, Vec<Block>>::with_capacity(3);
-    let mut blocks_iter = config.split("\n>");
-    let global_config = blocks_iter
-        .next()
-        .map(GlobalConfig::try_from)
-        .unwrap_or_else(|| Ok(Default::default()))?;
-    for block in blocks_iter {
-        let b: Block = Block::parse(block, monitor)?;
-        blocks.entry(b.alignment).or_default().push(b);
-    }
-    Ok((global_config, blocks))
+/// Parses `config`, the contents of the file at `path`, reporting every
+/// error in it instead of stopping at the first one.
+///
+/// Files ending in `.toml` are TOML configs, anything else is a lemonrc.
+/// Either can `include` other files of both kinds.
+pub fn parse(config: &str, path: &Path) -> Result<(GlobalConfig, Config), Diagnostics> {
+    let files = include::Files::load(path, config);
+    let (sections, errors) = include::sections(&files)?;
+    section::resolve(sections, &files, errors)
+}
+
+/// The config file at `path` and every file it includes.
+pub fn config_files(path: &Path) -> Vec<PathBuf> {
+    match std::fs::read_to_string(path) {
+        Ok(source) => include::Files::load(path, &source)
+            .paths()
+            .map(Path::to_path_buf)
+            .collect(),
+        Err(_) => vec![path.to_path_buf()],
+    }
+}
+
+pub fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "toml")
+}
+
+/// Splits a lemonrc into its global section and the `>` separated sections
+/// after it, which are blocks or, if the `>` is followed by `style:<name>`,
+/// styles. A `>` followed by `include:<path>` includes other files there.
+fn lemonrc_sections(config: &str) -> Vec<Section<'_>> {
+    let (global, parts) = lemonrc_parts(config);
+    let mut sections = vec![lemonrc_section(Kind::Global, &global[..0], global, |v| {
+        v.trim_matches('\'')
+    })];
+    for part in parts {
+        let (header, options) = part.split_once('\n').unwrap_or((part, ""));
+        if header.trim_start().starts_with("include:") {
+            sections.push(lemonrc_section(Kind::Include, header, part, |v| {
+                v.trim_end_matches('\'')
+            }));
+            continue;
+        }
+        let kind = match header.trim().strip_prefix("style:") {
+            Some(name) => Kind::Style(name.trim()),
+            None => Kind::Block,
+        };
+        sections.push(lemonrc_section(kind, header, options, |v| {
+            v.trim_end_matches('\'')
+        }));
+    }
+    sections
+}
+
+/// The global section of a lemonrc and the `>` separated sections after it,
+/// without their `>`.
+fn lemonrc_parts(config: &str) -> (&str, impl Iterator<Item = &str>) {
+    // Files with no global options, like included ones, can start with `>`.
+    let (global, parts) = match config.strip_prefix('>') {
+        Some(parts) => (&config[..0], Some(parts)),
+        None => match config.split_once("\n>") {
+            Some((global, parts)) => (global, Some(parts)),
+            None => (config, None),
+        },
+    };
+    (global, parts.into_iter().flat_map(|p| p.split("\n>")))
+}
+
+fn lemonrc_section<'a>(
+    kind: Kind<'a>,
+    header: &'a str,
+    options: &'a str,
+    trim: impl Fn(&'a str) -> &'a str,
+) -> Section<'a> {
+    let mut section = Section {
+        kind,
+        header,
+        entries: vec![],
+        variables: vec![],
+        errors: vec![],
+    };
+    for opt in options.split('\n').filter(|s| !s.trim().is_empty()) {
+        if let (Kind::Global, Some(variable)) =
+            (&section.kind, opt.trim_start().strip_prefix("set "))
+        {
+            match variable.split_once('=') {
+                Some((name, value)) => section
+                    .variables
+                    .push(Entry::new(name.trim(), trim(value.trim()))),
+                None => section
+                    .errors
+                    .push((opt, "Expected a variable as 'set <name> = <value>'").into()),
+            }
+            continue;
+        }
+        match split_option(opt) {
+            Ok((key, value)) => section.entries.push(Entry::new(key, trim(value))),
+            Err(e) => section.errors.push(e),
+        }
+    }
+    section
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
@@ -50,6 +146,29 @@ impl FromStr for Alignment {
     }
 }
 
+impl Display for Alignment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Left => write!(f, "left"),
+            Self::Middle => write!(f, "middle"),
+            Self::Right => write!(f, "right"),
+        }
+    }
+}
+
+/// How the output of a block's command is read.
+#[derive(Copy, Clone, Default, PartialEq)]
+enum Format {
+    /// Plain text, or markup if the block is `raw` or uses `markup`.
+    #[default]
+    Text,
+    /// Objects of the i3bar protocol, with their own colours.
+    Json,
+    /// Lines of i3blocks output: the full text, the short text and the
+    /// colour, made into i3bar objects.
+    I3blocks,
+}
+
 enum Content {
     Static(String),
     Cmd {
@@ -63,40 +182,6 @@ enum Content {
 }
 
 impl Content {
-    fn update(&mut self) {
-        if let Self::Cmd { cmd, last_run } = self {
-            for m in 0..last_run.len() {
-                match Command::new("sh")
-                    .args(&["-c", cmd])
-                    .env("MONITOR", m.to_string())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::inherit())
-                    .spawn()
-                    .and_then(|c| c.wait_with_output())
-                    .and_then(|o| {
-                        if o.status.success() {
-                            Ok(o.stdout)
-                        } else {
-                            Err(io::Error::from(io::ErrorKind::InvalidInput))
-                        }
-                    })
-                    .map_err(|e| e.to_string())
-                    .and_then(|o| String::from_utf8(o).map_err(|e| e.to_string()))
-                    .map(|mut l| {
-                        if let Some(i) = l.find('\n') {
-                            l.truncate(i);
-                            l
-                        } else {
-                            l
-                        }
-                    }) {
-                    Ok(o) => last_run[m] = o,
-                    Err(e) => last_run[m] = e,
-                }
-            }
-        }
-    }
-
     fn is_empty(&self, monitor: usize) -> bool {
         match self {
             Self::Static(s) => s.is_empty(),
@@ -105,164 +190,372 @@ impl Content {
         }
     }
 
-    fn replicate_to_mon(mut self, n_monitor: usize) -> Self {
-        match &mut self {
-            Self::Cmd { last_run, .. } => {
-                while last_run.len() < n_monitor {
-                    last_run.push(String::new());
-                }
-            }
-            Self::Persistent { last_run, .. } => {
-                while last_run.len() < n_monitor {
-                    last_run.push(String::new());
-                }
-            }
+    fn replicate_to_mon(&mut self, n_monitor: usize) {
+        match self {
+            Self::Cmd { last_run, .. } => last_run.resize(n_monitor),
+            Self::Persistent { last_run, .. } => last_run.resize(n_monitor),
             _ => (),
         }
-        self
     }
 
-    fn take(&mut self, mon: usize) -> Option<String> {
-        let f = |s: &mut String| {
+    fn get(&self, mon: usize) -> Option<String> {
+        let f = |s: &String| {
             if !s.is_empty() {
-                Some(mem::replace(s, String::new()))
+                Some(s.clone())
             } else {
                 None
             }
         };
         match self {
             Self::Static(s) => Some(s.clone()),
-            Self::Cmd { last_run, .. } => f(&mut last_run[mon]),
-            Self::Persistent { last_run, .. } => f(&mut last_run[mon]),
+            Self::Cmd { last_run, .. } => f(&last_run[mon]),
+            Self::Persistent { last_run, .. } => f(&last_run[mon]),
+        }
+    }
+
+    fn set(&mut self, mon: usize, output: String) -> bool {
+        match self {
+            // Only set from outside, e.g. over the socket.
+            Self::Static(s) if *s != output => {
+                *s = output;
+                true
+            }
+            Self::Static(_) => false,
+            Self::Cmd { last_run, .. } | Self::Persistent { last_run, .. } => {
+                // Updates for monitors that have since been unplugged are dropped.
+                match last_run.get_mut(mon) {
+                    Some(last) if *last != output => {
+                        *last = output;
+                        true
+                    }
+                    _ => false,
+                }
+            }
         }
     }
 }
 
 pub struct Block {
+    /// Set with `name`, or `block-<n>` for the n-th block of the config.
+    name: String,
     bg: Option<Color>,
     fg: Option<Color>,
     un: Option<Color>,
+    underline: bool,
+    overline: bool,
     font: Option<Font>,
     offset: Option<f64>,
     actions: [Option<String>; 5],
     content: Content,
     interval: Duration,
-    timer: RwLock<Duration>,
     alignment: Alignment,
     raw: bool,
-    signal: bool,
+    pango: bool,
+    format: Format,
+    signal: Option<u8>,
+    multi_monitor: bool,
+    separator: bool,
+    only_if: Option<String>,
+    hostnames: Option<Vec<String>>,
+    monitors: Option<Vec<usize>>,
+    recheck: bool,
+    /// Hidden over the socket, until shown again or reloaded.
+    hidden: bool,
+    /// Why the command failed on each monitor it last failed on, shown
+    /// instead of its output.
+    errors: HashMap<usize, Failure>,
 }
 
 impl Block {
-    pub fn to_text(&mut self, monitor: usize) -> Option<Text> {
-        self.content.take(monitor).map(|text| {
-            let mut attr = Attributes::default();
-            if let Some(fg) = self.fg {
-                attr = attr.with_fg_color(fg);
-            }
-            if let Some(bg) = self.bg {
-                attr = attr.with_bg_color(bg);
+    /// The spans of text to draw for `monitor`, styled on top of the global
+    /// config's. Raw and JSON blocks can have many spans, others have at most
+    /// one. JSON and i3blocks blocks use their short texts if `short` is
+    /// set.
+    pub fn to_texts(&self, monitor: usize, global_config: &GlobalConfig, short: bool) -> Vec<Text> {
+        if let Some(failure) = self.errors.get(&self.slot(monitor)) {
+            return vec![Text {
+                attr: global_config.error_attributes(),
+                text: format!("{} {}", global_config.error_marker(), failure.summary),
+                actions: Default::default(),
+                markup: false,
+            }];
+        }
+        let text = match self.content.get(monitor) {
+            Some(text) => text,
+            None => return vec![],
+        };
+        let mut attr = global_config.attributes();
+        if let Some(fg) = self.fg {
+            attr = attr.with_fg_color(fg);
+        }
+        if let Some(bg) = self.bg {
+            attr = attr.with_bg_color(bg);
+        }
+        if let Some(font) = &self.font {
+            attr = attr.with_font(font.clone());
+        }
+        if let Some(un) = self.un {
+            attr = attr.with_line_color(un);
+        }
+        attr.underline = self.underline;
+        attr.overline = self.overline;
+        let separator = global_config.separator.as_ref().map(|s| Text {
+            attr: global_config.attributes(),
+            text: s.clone(),
+            actions: Default::default(),
+            markup: false,
+        });
+        let json = match self.format {
+            Format::Json | Format::I3blocks => {
+                i3bar::to_texts(&text, &attr, short, separator.as_ref())
             }
-            if let Some(font) = &self.font {
-                attr = attr.with_font(font.clone());
+            Format::Text => None,
+        };
+        // Lines that aren't valid JSON are shown as they are.
+        let mut texts = match json {
+            Some(texts) => texts,
+            None if self.raw => markup::parse(&text, &attr, &global_config.fonts),
+            None => vec![Text {
+                attr,
+                text,
+                actions: Default::default(),
+                markup: self.pango,
+            }],
+        };
+        if let (Some(offset), Some(first)) = (self.offset, texts.first_mut()) {
+            match self.alignment {
+                Alignment::Left => first.attr.padding = first.attr.padding.with_left(offset),
+                Alignment::Right => first.attr.padding = first.attr.padding.with_left(offset),
+                Alignment::Middle => (),
             }
-            if let Some(offset) = self.offset {
-                match self.alignment {
-                    Alignment::Left => attr = attr.with_padding(Padding::left(offset)),
-                    Alignment::Right => attr = attr.with_padding(Padding::left(offset)),
-                    Alignment::Middle => (),
-                }
+        }
+        texts
+    }
+
+    /// Whether this block has nothing to show on `monitor`.
+    pub fn is_empty(&self, monitor: usize) -> bool {
+        self.hidden
+            || self
+                .monitors
+                .as_ref()
+                .is_some_and(|m| !m.contains(&monitor))
+            || (!self.errors.contains_key(&self.slot(monitor)) && self.content.is_empty(monitor))
+    }
+
+    /// Hides the block or shows it again, returning whether that changed.
+    pub fn set_hidden(&mut self, hidden: bool) -> bool {
+        std::mem::replace(&mut self.hidden, hidden) != hidden
+    }
+
+    /// The last output for `monitor`, if any.
+    pub fn output(&self, monitor: usize) -> Option<String> {
+        self.content.get(monitor)
+    }
+
+    /// Why the command failed, for every monitor it currently fails on.
+    pub fn errors(&self) -> Vec<(usize, &Failure)> {
+        let mut errors = self.errors.iter().map(|(&m, f)| (m, f)).collect::<Vec<_>>();
+        errors.sort_by_key(|&(monitor, _)| monitor);
+        errors
+    }
+
+    /// Whether this block's `hostname` and `only-if` conditions hold, which
+    /// decides if it's part of the bar at all. A rechecked `only-if` is
+    /// left to the block's task instead.
+    pub fn is_shown(&self) -> bool {
+        if let Some(hostnames) = &self.hostnames {
+            if !hostname().is_some_and(|h| hostnames.contains(&h)) {
+                return false;
             }
-            Text { attr, text }
-        })
-    }
-
-    pub fn update(&mut self) {
-        self.content.update();
-    }
-
-    fn parse(block: &str, n_monitor: usize) -> Result<Self, ParseError> {
-        use BlockBuilder as BB;
-        let mut block_b = BB::default();
-        for opt in block.split('\n').skip(1).filter(|s| !s.trim().is_empty()) {
-            let (key, value) = opt.split_at(opt.find(':').ok_or((opt, "missing :"))?);
-            let value = value[1..].trim().trim_end_matches('\'');
-            let color = || Color::from_str(value).map_err(|e| (opt, e.to_string()));
-            block_b = match key
-                .trim()
-                .trim_start_matches('*')
-                .trim_start_matches('-')
-                .trim()
-            {
-                "background" | "bg" => BB {
-                    bg: Some(color()?),
-                    ..block_b
-                },
-                "foreground" | "fg" => BB {
-                    fg: Some(color()?),
-                    ..block_b
-                },
-                "underline" | "un" => BB {
-                    un: Some(color()?),
-                    ..block_b
-                },
-                "font" => BB {
-                    font: Some(value.into()),
-                    ..block_b
-                },
-                "offset" => BB {
-                    offset: Some(value.parse().map_err(|_| (opt, "Invalid offset"))?),
-                    ..block_b
-                },
-                "left-click" => block_b.action(0, value),
-                "middle-click" => block_b.action(1, value),
-                "right-click" => block_b.action(2, value),
-                "scroll-up" => block_b.action(3, value),
-                "scroll-down" => block_b.action(4, value),
-                "interval" => BB {
-                    interval: Some(Duration::from_secs(
-                        value
-                            .parse::<u64>()
-                            .map_err(|_| (opt, "Invalid duration"))?,
-                    )),
-                    ..block_b
-                },
-                "command" | "cmd" => block_b.content_command(value),
-                "static" => block_b.content_static(value),
-                "persistent" => block_b.content_persistent(value),
-                "alignment" | "align" => BB {
-                    alignment: Some(value.parse().map_err(|e| (opt, e))?),
-                    ..block_b
-                },
-                "signal" => BB {
-                    signal: value.parse().map_err(|_| (opt, "Invalid boolean"))?,
-                    ..block_b
-                },
-                "raw" => BB {
-                    raw: value.parse().map_err(|_| (opt, "Invalid boolean"))?,
-                    ..block_b
+        }
+        match &self.only_if {
+            Some(cmd) if !self.recheck => succeeds(cmd, &self.name, None),
+            _ => true,
+        }
+    }
+
+    /// Whether the global separator can be drawn before this block.
+    pub fn separator_before(&self) -> bool {
+        self.separator
+    }
+
+    /// Whether the global separator can be drawn after this block on
+    /// `monitor`, which the last object of i3bar output can also forbid.
+    pub fn separator_after(&self, monitor: usize) -> bool {
+        self.separator
+            && (self.format == Format::Text
+                || self.errors.contains_key(&self.slot(monitor))
+                || self
+                    .content
+                    .get(monitor)
+                    .is_none_or(|l| i3bar::separator_after(&l)))
+    }
+
+    /// Where the output for `monitor` is kept, as blocks that aren't
+    /// `multi_monitor` only run for the first one.
+    fn slot(&self, monitor: usize) -> usize {
+        if self.multi_monitor {
+            monitor
+        } else {
+            0
+        }
+    }
+
+    /// Makes room for the output of `n_monitor` monitors, if this block has
+    /// different output per monitor.
+    pub fn set_monitors(&mut self, n_monitor: usize) {
+        if self.multi_monitor {
+            self.content.replicate_to_mon(n_monitor);
+        }
+    }
+
+    /// Whether this block's output comes from the same command, run the same
+    /// way and with the same name, as `other`'s, so one's task can be kept for the other.
+    pub fn runs_like(&self, other: &Block) -> bool {
+        match (&self.content, &other.content) {
+            (
+                Content::Cmd { cmd, last_run },
+                Content::Cmd {
+                    cmd: c,
+                    last_run: l,
                 },
-                "multi_monitor" => BB {
-                    multi_monitor: value.parse().map_err(|_| (opt, "Invalid boolean"))?,
-                    ..block_b
+            ) => {
+                cmd == c
+                    && self.name == other.name
+                    && self.format == other.format
+                    && last_run.len() == l.len()
+                    && self.interval == other.interval
+                    && self.recheck == other.recheck
+                    && self.only_if == other.only_if
+            }
+            (
+                Content::Persistent { cmd, last_run },
+                Content::Persistent {
+                    cmd: c,
+                    last_run: l,
                 },
-                s => {
-                    eprintln!("Warning: unrecognised option '{}', skipping", s);
-                    block_b
+            ) => {
+                cmd == c
+                    && self.name == other.name
+                    && self.format == other.format
+                    && last_run.len() == l.len()
+            }
+            _ => false,
+        }
+    }
+
+    /// Takes over the output `old` produced, and its failures, for when its
+    /// task is kept.
+    pub fn keep_output(&mut self, old: &mut Block) {
+        std::mem::swap(&mut self.content, &mut old.content);
+        std::mem::swap(&mut self.errors, &mut old.errors);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The offset from `SIGRTMIN` that makes this block update immediately.
+    pub fn signal(&self) -> Option<u8> {
+        self.signal
+    }
+
+    /// The action bound to X mouse `button` (1 to 5), if any.
+    pub fn action(&self, button: u8) -> Option<&str> {
+        self.actions
+            .get(usize::from(button).wrapping_sub(1))
+            .and_then(|a| a.as_deref())
+    }
+
+    /// Runs `action` in the background, as if it was triggered by clicking
+    /// this block with `button`. If the command failed on `monitor`, the
+    /// failure is in `BLOCK_ERROR`, e.g. to show it in a notification.
+    pub fn run_action(&self, action: &str, button: u8, index: usize, monitor: usize) {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", action])
+            .env("MONITOR", monitor.to_string())
+            .env("BUTTON", button.to_string())
+            .env("BLOCK_ALIGNMENT", self.alignment.to_string())
+            .env("BLOCK_INDEX", index.to_string())
+            .env("BLOCK_NAME", &self.name);
+        if let Some(failure) = self.errors.get(&self.slot(monitor)) {
+            command.env("BLOCK_ERROR", &failure.message);
+        }
+        match command.spawn() {
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            }
+            Err(e) => eprintln!(
+                "Block '{}': failed to run action '{}': {}",
+                self.name, action, e
+            ),
+        }
+    }
+
+    /// Replaces the output for `monitor`, or marks the command as failed on
+    /// it until its next output, returning whether that changed.
+    pub fn set_output(&mut self, monitor: usize, output: Result<String, Failure>) -> bool {
+        let slot = self.slot(monitor);
+        let output = match output {
+            Ok(output) => output,
+            Err(failure) => {
+                // Commands failing the same way every interval are only
+                // logged once.
+                if self.errors.get(&slot) == Some(&failure) {
+                    return false;
                 }
-            };
+                eprintln!("Block '{}': {}", self.name, failure.message);
+                self.errors.insert(slot, failure);
+                return true;
+            }
+        };
+        let failed = self.errors.remove(&slot).is_some();
+        if self.format == Format::Json && i3bar::is_framing(&output) {
+            return false;
         }
-        block_b
-            .build(n_monitor)
-            .map_err(|e| ("BLOCK DEFINITION", e).into())
+        // Markup is checked once here rather than on every render.
+        let output = match self.format {
+            Format::Text if self.pango => text::check_markup(output),
+            Format::Text => output,
+            Format::Json | Format::I3blocks => i3bar::check_markup(output),
+        };
+        self.content.set(monitor, output) || failed
+    }
+}
+
+/// Whether `cmd` exits successfully, for the `only-if` condition of the block
+/// called `name`. `monitor` is given when it's checked for one.
+fn succeeds(cmd: &str, name: &str, monitor: Option<usize>) -> bool {
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", cmd])
+        .env("BLOCK_NAME", name)
+        .stdout(Stdio::null());
+    if let Some(monitor) = monitor {
+        command.env("MONITOR", monitor.to_string());
+    }
+    command.status().is_ok_and(|s| s.success())
+}
+
+/// The name of this machine, for `hostname` conditions.
+fn hostname() -> Option<String> {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return None;
     }
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8(name[..len].to_vec()).ok()
 }
 
 #[derive(Default)]
 pub struct BlockBuilder {
+    name: Option<String>,
     bg: Option<Color>,
     fg: Option<Color>,
     un: Option<Color>,
+    underline: bool,
+    overline: bool,
     font: Option<Font>,
     offset: Option<f64>,
     actions: [Option<String>; 5],
@@ -270,134 +563,440 @@ pub struct BlockBuilder {
     interval: Option<Duration>,
     alignment: Option<Alignment>,
     raw: bool,
-    signal: bool,
+    pango: bool,
+    format: Format,
+    signal: Option<u8>,
     multi_monitor: bool,
+    separator: Option<bool>,
+    only_if: Option<String>,
+    hostnames: Option<Vec<String>>,
+    monitors: Option<Vec<usize>>,
+    recheck: bool,
 }
 
-impl BlockBuilder {
-    fn action(mut self, index: usize, action: &str) -> Self {
-        self.actions[index] = Some(action.into());
-        self
-    }
+/// Every option a block accepts, for suggestions when one isn't recognised.
+const BLOCK_OPTIONS: &[&str] = &[
+    "name",
+    "background",
+    "bg",
+    "foreground",
+    "fg",
+    "underline",
+    "un",
+    "overline",
+    "ov",
+    "font",
+    "offset",
+    "left-click",
+    "middle-click",
+    "right-click",
+    "scroll-up",
+    "scroll-down",
+    "interval",
+    "command",
+    "cmd",
+    "static",
+    "persistent",
+    "alignment",
+    "align",
+    "signal",
+    "raw",
+    "markup",
+    "format",
+    "separator",
+    "multi_monitor",
+    "only-if",
+    "hostname",
+    "monitor",
+    "recheck",
+    "inherit",
+];
 
-    fn content_command(self, c: &str) -> Self {
-        Self {
-            content: Some(Content::Cmd {
-                cmd: c.to_string(),
-                last_run: Default::default(),
-            }),
-            ..self
+impl BlockBuilder {
+    /// Sets the option `key` of a block definition to `value`.
+    fn option<'a>(&mut self, key: &'a str, value: &'a str) -> Result<(), ParseError<'a>> {
+        let color = || Color::from_str(value).map_err(|e| (value, e.to_string()));
+        let boolean = || value.parse().map_err(|_| (value, "Invalid boolean"));
+        match key {
+            // Names are given to `lemonjuice msg`, which splits on spaces.
+            "name" if value.is_empty() || value.contains(char::is_whitespace) => {
+                return Err((value, "Invalid name, expected a word without spaces").into())
+            }
+            "name" => self.name = Some(value.into()),
+            "background" | "bg" => self.bg = Some(color()?),
+            "foreground" | "fg" => self.fg = Some(color()?),
+            // Either toggles the line on, using the default colour, or
+            // turns it on with the given colour, like `%{U}%{+u}`.
+            "underline" | "un" => match value.parse() {
+                Ok(underline) => self.underline = underline,
+                Err(_) => {
+                    self.un = Some(color()?);
+                    self.underline = true;
+                }
+            },
+            "overline" | "ov" => match value.parse() {
+                Ok(overline) => self.overline = overline,
+                Err(_) => {
+                    self.un = Some(color()?);
+                    self.overline = true;
+                }
+            },
+            "font" => self.font = Some(value.into()),
+            "offset" => self.offset = Some(value.parse().map_err(|_| (value, "Invalid offset"))?),
+            "left-click" => self.action(0, value),
+            "middle-click" => self.action(1, value),
+            "right-click" => self.action(2, value),
+            "scroll-up" => self.action(3, value),
+            "scroll-down" => self.action(4, value),
+            "interval" => {
+                // Commands would be rerun without pause with no interval.
+                self.interval = Some(Duration::from_secs(
+                    value.parse::<u64>().ok().filter(|&secs| secs > 0).ok_or((
+                        value,
+                        "Invalid duration, expected a number of seconds above 0",
+                    ))?,
+                ))
+            }
+            "command" | "cmd" => {
+                self.content = Some(Content::Cmd {
+                    cmd: value.to_string(),
+                    last_run: Default::default(),
+                })
+            }
+            "static" => self.content = Some(Content::Static(value.to_string())),
+            "persistent" => {
+                self.content = Some(Content::Persistent {
+                    cmd: value.to_string(),
+                    last_run: Default::default(),
+                })
+            }
+            "alignment" | "align" => self.alignment = Some(value.parse().map_err(|e| (value, e))?),
+            "signal" => {
+                self.signal = match value {
+                    "true" => Some(0),
+                    "false" => None,
+                    n => Some(
+                        n.parse()
+                            .ok()
+                            .filter(|&n| crate::signal::is_valid_offset(n))
+                            .ok_or((value, "Invalid signal, expected a boolean or a number"))?,
+                    ),
+                }
+            }
+            "raw" => self.raw = boolean()?,
+            "markup" => {
+                let (raw, pango) = match value {
+                    "none" => (false, false),
+                    "lemonbar" => (true, false),
+                    "pango" => (false, true),
+                    _ => {
+                        return Err(
+                            (value, "Invalid markup, expected none, lemonbar or pango").into()
+                        )
+                    }
+                };
+                self.raw = raw;
+                self.pango = pango;
+            }
+            "format" => {
+                self.format = match value {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    "i3blocks" => Format::I3blocks,
+                    _ => {
+                        return Err(
+                            (value, "Invalid format, expected text, json or i3blocks").into()
+                        )
+                    }
+                }
+            }
+            "separator" => self.separator = Some(boolean()?),
+            "multi_monitor" => self.multi_monitor = boolean()?,
+            "only-if" => self.only_if = Some(value.into()),
+            "hostname" => {
+                self.hostnames = Some(value.split(',').map(|h| h.trim().into()).collect())
+            }
+            "monitor" => {
+                self.monitors = Some(
+                    value
+                        .split(',')
+                        .map(|m| m.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| (value, "Invalid monitor, expected a list of numbers"))?,
+                )
+            }
+            "recheck" => self.recheck = boolean()?,
+            key => return Err(ParseError::unknown_option(key, BLOCK_OPTIONS)),
         }
+        Ok(())
     }
 
-    fn content_static(self, c: &str) -> Self {
-        Self {
-            content: Some(Content::Static(c.to_string())),
-            ..self
-        }
+    fn action(&mut self, index: usize, action: &str) {
+        self.actions[index] = Some(action.into());
     }
 
-    fn content_persistent(self, c: &str) -> Self {
-        Self {
-            content: Some(Content::Persistent {
-                cmd: c.to_string(),
-                last_run: Default::default(),
-            }),
-            ..self
+    /// Builds the `n`-th block of the config, which is named after its
+    /// position if it has no `name`.
+    fn build(self, n: usize) -> Result<Block, &'static str> {
+        let name = self.name.unwrap_or_else(|| format!("block-{}", n));
+        if self.recheck && self.only_if.is_none() {
+            return Err("'recheck' needs an 'only-if' condition");
         }
-    }
-
-    fn build(self, n_monitor: usize) -> Result<Block, &'static str> {
-        let n_monitor = if self.multi_monitor { n_monitor } else { 1 };
-        if let Some(content) = self.content {
+        if let Some(mut content) = self.content {
+            if self.recheck && !matches!(content, Content::Cmd { .. }) {
+                return Err("'recheck' only works for blocks with a 'command'");
+            }
+            if let Content::Static(s) = &mut content {
+                match self.format {
+                    Format::Text if self.pango => *s = text::check_markup(std::mem::take(s)),
+                    Format::Text => (),
+                    Format::Json | Format::I3blocks => *s = i3bar::check_markup(std::mem::take(s)),
+                }
+            }
             if let Some(alignment) = self.alignment {
                 Ok(Block {
+                    name,
                     bg: self.bg,
                     fg: self.fg,
                     un: self.un,
+                    underline: self.underline,
+                    overline: self.overline,
                     font: self.font,
                     offset: self.offset,
-                    content: content.replicate_to_mon(n_monitor),
+                    content,
                     interval: self.interval.unwrap_or_else(|| Duration::from_secs(10)),
                     actions: self.actions,
                     alignment,
-                    timer: Default::default(),
                     raw: self.raw,
+                    pango: self.pango,
+                    format: self.format,
                     signal: self.signal,
+                    multi_monitor: self.multi_monitor,
+                    separator: self.separator.unwrap_or(true),
+                    only_if: self.only_if,
+                    hostnames: self.hostnames,
+                    monitors: self.monitors,
+                    recheck: self.recheck,
+                    hidden: false,
+                    errors: HashMap::new(),
                 })
             } else {
-                Err("No alignment defined")
+                Err("No alignment defined, expected 'alignment: left', 'middle' or 'right'")
             }
         } else {
-            Err("No content defined")
+            Err("No content defined, expected a 'command', 'persistent' or 'static' option")
+        }
+    }
+}
+
+/// The position of the block `id` refers to, given as its name or as
+/// `<alignment>:<index>`.
+pub fn find(config: &Config, id: &str) -> Result<(Alignment, usize), String> {
+    let named = config.iter().find_map(|(&alignment, blocks)| {
+        let index = blocks.iter().position(|b| b.name == id)?;
+        Some((alignment, index))
+    });
+    if let Some(position) = named {
+        return Ok(position);
+    }
+    let position = id
+        .split_once(':')
+        .and_then(|(alignment, index)| Some((alignment.parse().ok()?, index.parse().ok()?)));
+    match position {
+        Some((alignment, index)) if config.get(&alignment).is_some_and(|b| index < b.len()) => {
+            Ok((alignment, index))
+        }
+        _ => Err(format!(
+            "No block '{}', expected a name or <alignment>:<index>",
+            id
+        )),
+    }
+}
+
+/// A lemonbar style `WxH+X+Y` geometry, where every part is optional.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Geometry {
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    pub x: u16,
+    pub y: u16,
+}
+
+impl FromStr for Geometry {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+        let mut parts = s.trim().split('+');
+        let size = parts.next().unwrap_or_default();
+        let (width, height) = match size.find('x') {
+            Some(i) => (&size[..i], &size[i + 1..]),
+            None => (size, ""),
+        };
+        let dimension = |d: &str| {
+            if d.is_empty() {
+                Ok(None)
+            } else {
+                d.parse().map(Some).map_err(|_| "Invalid geometry size")
+            }
+        };
+        let position = |p: Option<&str>| {
+            p.filter(|p| !p.is_empty())
+                .map(|p| p.parse().map_err(|_| "Invalid geometry position"))
+                .unwrap_or(Ok(0))
+        };
+        let geometry = Geometry {
+            width: dimension(width)?,
+            height: dimension(height)?,
+            x: position(parts.next())?,
+            y: position(parts.next())?,
+        };
+        if parts.next().is_some() {
+            Err("Invalid geometry, expected WxH+X+Y")
+        } else {
+            Ok(geometry)
         }
     }
 }
 
 #[derive(Default)]
 pub struct GlobalConfig {
-    base_geometry: Option<String>,
-    bars_geometries: Vec<String>,
-    bottom: bool,
-    font: Option<Font>,
+    pub base_geometry: Geometry,
+    pub bars_geometries: Vec<Geometry>,
+    pub bottom: bool,
+    pub font: Option<Font>,
+    /// Every font given, for `%{T}` tags in raw blocks.
+    pub fonts: Vec<Font>,
     n_clickbles: Option<u32>,
-    name: Option<String>,
+    pub name: Option<String>,
     underline_width: Option<u32>,
-    background: Option<Color>,
-    foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub foreground: Option<Color>,
     underline: Option<Color>,
-    separator: Option<String>,
-    tray: bool,
+    pub separator: Option<String>,
+    /// The section the system tray is shown in, if any.
+    pub tray: Option<Alignment>,
+    error_background: Option<Color>,
+    error_foreground: Option<Color>,
+    error_marker: Option<String>,
 }
 
-impl<'a> TryFrom<&'a str> for GlobalConfig {
-    type Error = ParseError<'a>;
-    fn try_from(globals: &'a str) -> Result<Self, Self::Error> {
-        let mut global_config = Self::default();
-        for opt in globals.split('\n').filter(|s| !s.trim().is_empty()) {
-            let (key, value) = opt.split_at(opt.find(':').ok_or((opt, "missing :"))?);
-            let value = value[1..].trim_matches('\'');
-            let color = || Color::from_str(value).map_err(|e| (opt, e.to_string()));
-            match key
-                .trim()
-                .trim_start_matches('*')
-                .trim_start_matches('-')
-                .trim()
-            {
-                "background" | "bg" | "B" => global_config.background = Some(color()?),
-                "foreground" | "fg" | "F" => global_config.foreground = Some(color()?),
-                "underline" | "un" | "U" => global_config.underline = Some(color()?),
-                "font" | "f" => global_config.font = Some(value.into()),
-                "bottom" | "b" => {
-                    global_config.bottom = value
-                        .trim()
-                        .parse()
-                        .map_err(|_| (opt, "Not a valid boolean"))?
-                }
-                "n_clickables" | "a" => {
-                    global_config.n_clickbles = Some(
-                        value
-                            .trim()
-                            .parse()
-                            .map_err(|_| (opt, "Not a valid number"))?,
-                    )
+impl GlobalConfig {
+    /// The attributes every block starts from before applying its own.
+    pub fn attributes(&self) -> Attributes {
+        let mut attr = Attributes::default();
+        if let Some(fg) = self.foreground {
+            attr = attr.with_fg_color(fg);
+        }
+        if let Some(bg) = self.background {
+            attr = attr.with_bg_color(bg);
+        }
+        if let Some(font) = &self.font {
+            attr = attr.with_font(font.clone());
+        }
+        if let Some(underline) = self.underline {
+            attr = attr.with_line_color(underline);
+        }
+        if let Some(width) = self.underline_width {
+            attr.line_width = f64::from(width);
+        }
+        attr
+    }
+
+    /// The attributes of failed blocks and config errors, white on red
+    /// unless set otherwise.
+    pub fn error_attributes(&self) -> Attributes {
+        self.attributes()
+            .with_fg_color(self.error_foreground.unwrap_or_else(Color::white))
+            .with_bg_color(self.error_background.unwrap_or_else(Color::red))
+    }
+
+    /// What failed blocks show before what went wrong.
+    fn error_marker(&self) -> &str {
+        self.error_marker.as_deref().unwrap_or("!")
+    }
+}
+
+/// Splits a `key: value` line of a lemonrc, ignoring the `*` and `-` a key
+/// may be prefixed with.
+fn split_option(opt: &str) -> Result<(&str, &str), ParseError<'_>> {
+    let (key, value) = opt.split_at(opt.find(':').ok_or((opt, "missing :"))?);
+    let key = key
+        .trim()
+        .trim_start_matches('*')
+        .trim_start_matches('-')
+        .trim();
+    Ok((key, value[1..].trim()))
+}
+
+/// Every option the global section accepts, for suggestions when one isn't
+/// recognised.
+const GLOBAL_OPTIONS: &[&str] = &[
+    "background",
+    "bg",
+    "foreground",
+    "fg",
+    "underline",
+    "un",
+    "font",
+    "bottom",
+    "n_clickables",
+    "underline_width",
+    "separator",
+    "geometry",
+    "bars_geometries",
+    "geometries",
+    "name",
+    "tray",
+    "error_background",
+    "error_bg",
+    "error_foreground",
+    "error_fg",
+    "error_marker",
+];
+
+impl GlobalConfig {
+    /// Sets the global option `key` to `value`.
+    fn option<'a>(&mut self, key: &'a str, value: &'a str) -> Result<(), ParseError<'a>> {
+        let color = || Color::from_str(value).map_err(|e| (value, e.to_string()));
+        let number = || value.parse().map_err(|_| (value, "Not a valid number"));
+        match key {
+            "background" | "bg" | "B" => self.background = Some(color()?),
+            "foreground" | "fg" | "F" => self.foreground = Some(color()?),
+            "underline" | "un" | "U" => self.underline = Some(color()?),
+            // Like lemonbar, the first font is the default one.
+            "font" | "f" => {
+                let font = Font::from(value);
+                if self.font.is_none() {
+                    self.font = Some(font.clone());
                 }
-                "underline_width" | "u" => {
-                    global_config.underline_width = Some(
-                        value
-                            .trim()
-                            .parse()
-                            .map_err(|_| (opt, "Not a valid number"))?,
-                    )
+                self.fonts.push(font);
+            }
+            "bottom" | "b" => {
+                self.bottom = value.parse().map_err(|_| (value, "Not a valid boolean"))?
+            }
+            "n_clickables" | "a" => self.n_clickbles = Some(number()?),
+            "underline_width" | "u" => self.underline_width = Some(number()?),
+            "separator" => self.separator = Some(value.into()),
+            "geometry" | "g" => self.base_geometry = value.parse().map_err(|e| (value, e))?,
+            "bars_geometries" | "geometries" => {
+                for geometry in value.split(',') {
+                    let geometry = geometry.trim();
+                    self.bars_geometries
+                        .push(geometry.parse().map_err(|e| (geometry, e))?);
                 }
-                "separator" => global_config.separator = Some(value.into()),
-                "geometry" | "g" => global_config.base_geometry = Some(value.into()),
-                "name" | "n" => global_config.name = Some(value.into()),
-                s => {
-                    eprintln!("Warning: unrecognised option '{}', skipping", s);
+            }
+            "name" | "n" => self.name = Some(value.into()),
+            "tray" => {
+                self.tray = match value {
+                    "true" => Some(Alignment::Right),
+                    "false" => None,
+                    alignment => Some(alignment.parse().map_err(|e| (value, e))?),
                 }
             }
+            "error_background" | "error_bg" => self.error_background = Some(color()?),
+            "error_foreground" | "error_fg" => self.error_foreground = Some(color()?),
+            "error_marker" => self.error_marker = Some(value.into()),
+            key => return Err(ParseError::unknown_option(key, GLOBAL_OPTIONS)),
         }
-        Ok(global_config)
+        Ok(())
     }
 }
@@ -1,24 +1,216 @@
-use std::{error::Error, fmt::{self, Display},borrow::Cow};
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt::{self, Display},
+    path::Path,
+};
 
+/// A problem with the part of a config that `span` points into.
 #[derive(Debug, Clone)]
-pub struct ParseError<'a>(&'a str, Cow<'a, str>);
+pub struct ParseError<'a> {
+    span: &'a str,
+    message: Cow<'a, str>,
+    help: Option<String>,
+}
+
+impl<'a> ParseError<'a> {
+    /// Adds a hint on how to fix the error.
+    pub fn with_help(self, help: String) -> Self {
+        Self {
+            help: Some(help),
+            ..self
+        }
+    }
+
+    /// Points the error at `span` instead.
+    pub fn with_span(self, span: &'a str) -> Self {
+        Self { span, ..self }
+    }
+
+    /// Whether the error points into `text`.
+    pub fn is_in(&self, text: &str) -> bool {
+        let start = text.as_ptr() as usize;
+        (start..=start + text.len()).contains(&(self.span.as_ptr() as usize))
+    }
+
+    /// Moves the error from the text it was found in to where that text
+    /// came from, given as pairs of the text and its origin. Errors in none
+    /// of them are moved to the last origin.
+    pub fn relocate<'b>(self, origins: &[(&str, &'b str)]) -> ParseError<'b> {
+        let origin = origins.iter().find(|(text, _)| self.is_in(text));
+        // Text that is its own origin can keep the exact span.
+        if let Some((text, origin)) =
+            origin.filter(|(t, o)| t.as_ptr() == o.as_ptr() && t.len() == o.len())
+        {
+            let offset = self.span.as_ptr() as usize - text.as_ptr() as usize;
+            let end = (offset + self.span.len()).min(origin.len());
+            return ParseError {
+                span: &origin[offset..end],
+                message: self.message.into_owned().into(),
+                help: self.help,
+            };
+        }
+        let span = origin
+            .or_else(|| origins.last())
+            .map_or("", |(_, origin)| origin);
+        ParseError {
+            span,
+            message: self.message.into_owned().into(),
+            help: self.help,
+        }
+    }
+
+    /// Makes an error for an option that isn't one of `known`, suggesting
+    /// the closest one.
+    pub fn unknown_option(key: &'a str, known: &[&str]) -> Self {
+        Self::unknown("option", key, known)
+    }
+
+    /// Makes an error for a `what` that isn't one of `known`, suggesting the
+    /// closest one.
+    pub fn unknown(what: &str, key: &'a str, known: &[&str]) -> Self {
+        let error = Self::from((key, format!("Unrecognised {} '{}'", what, key)));
+        match did_you_mean(key, known) {
+            Some(k) => error.with_help(format!("did you mean '{}'?", k)),
+            None => error,
+        }
+    }
+}
 
 impl<'a> From<(&'a str, &'a str)> for ParseError<'a> {
     fn from(t: (&'a str, &'a str)) -> Self {
-        Self(t.0, t.1.into())
+        Self {
+            span: t.0,
+            message: t.1.into(),
+            help: None,
+        }
     }
 }
 
 impl<'a> From<(&'a str, String)> for ParseError<'a> {
     fn from(e: (&'a str, String)) -> Self {
-        Self(e.0, e.1.into())
+        Self {
+            span: e.0,
+            message: e.1.into(),
+            help: None,
+        }
+    }
+}
+
+/// The option in `known` that `key` was most likely meant to be, if any is
+/// close enough.
+fn did_you_mean<'k>(key: &str, known: &[&'k str]) -> Option<&'k str> {
+    let normalise = |k: &str| k.to_lowercase().replace('-', "_");
+    let key = normalise(key);
+    known
+        .iter()
+        .map(|&k| (distance(&key, &normalise(k)), k))
+        .filter(|&(d, _)| d <= 2 && d < key.len())
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, k)| k)
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// A [`ParseError`] located in the file it came from.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    path: String,
+    line: usize,
+    column: usize,
+    /// The part of the file the error is in, like `block 3`.
+    section: Option<String>,
+    snippet: String,
+    width: usize,
+    message: String,
+    help: Option<String>,
+}
+
+impl Diagnostic {
+    /// Locates `error`, whose span points into `source`, the contents of the
+    /// file at `path`.
+    pub fn new(error: ParseError<'_>, path: &Path, source: &str, section: Option<String>) -> Self {
+        let start = source.as_ptr() as usize;
+        let offset = (error.span.as_ptr() as usize)
+            .checked_sub(start)
+            .filter(|&o| o <= source.len())
+            .unwrap_or(0);
+        let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |i| offset + i);
+        Self {
+            path: path.display().to_string(),
+            line: source[..offset].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            section,
+            snippet: source[line_start..line_end].to_string(),
+            width: error
+                .span
+                .lines()
+                .next()
+                .map_or(0, |l| l.chars().count())
+                .max(1),
+            message: error.message.into_owned(),
+            help: error.help,
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: ", self.path, self.line, self.column)?;
+        match &self.section {
+            Some(section) => writeln!(f, "error in {}: {}", section, self.message)?,
+            None => writeln!(f, "error: {}", self.message)?,
+        }
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.snippet)?;
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(self.column - 1),
+            "^".repeat(self.width)
+        )?;
+        if let Some(help) = &self.help {
+            write!(f, "\n{} = help: {}", gutter, help)?;
+        }
+        Ok(())
     }
 }
 
-impl<'a> Display for ParseError<'a> {
+impl Error for Diagnostic {}
+
+/// Every error found in a config.
+#[derive(Debug, Clone)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Display for Diagnostics {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "tried to parse '{}' but {}", self.0, self.1)
+        for diagnostic in &self.0 {
+            writeln!(f, "{}\n", diagnostic)?;
+        }
+        match self.0.len() {
+            1 => write!(f, "1 error found"),
+            n => write!(f, "{} errors found", n),
+        }
     }
 }
 
-impl<'a> Error for ParseError<'a> {}
+impl Error for Diagnostics {}
@@ -0,0 +1,204 @@
+use crate::text::{self, color::Color, Align, Attributes, MinWidth, Text};
+use serde_json::{Map, Value};
+use std::{mem, str::FromStr};
+
+/// Whether `line` is part of the i3bar protocol's framing instead of a status
+/// line: its header or the opening of its endless array.
+pub fn is_framing(line: &str) -> bool {
+    let line = line.trim();
+    line == "["
+        || serde_json::from_str::<Map<String, Value>>(line).is_ok_and(|o| o.contains_key("version"))
+}
+
+/// Makes a status line out of the output of an i3blocks command, whose lines
+/// are its full text, short text and colour, and which is `urgent` if it
+/// exited with 33. Empty full texts hide the block.
+pub fn from_i3blocks(output: &str, urgent: bool) -> String {
+    let mut lines = output.lines();
+    let full_text = lines.next().unwrap_or_default();
+    if full_text.is_empty() {
+        return String::new();
+    }
+    let mut object = Map::new();
+    object.insert("full_text".into(), full_text.into());
+    for key in ["short_text", "color"] {
+        if let Some(line) = lines.next().filter(|l| !l.is_empty()) {
+            object.insert(key.into(), line.into());
+        }
+    }
+    if urgent {
+        object.insert("urgent".into(), true.into());
+    }
+    Value::Object(object).to_string()
+}
+
+/// Checks the text of the objects of a status line that use Pango markup,
+/// escaping it if it's invalid, so that it's only checked once.
+pub fn check_markup(line: String) -> String {
+    let mut value = match serde_json::from_str(line.trim().trim_start_matches(',')) {
+        Ok(value) => value,
+        Err(_) => return line,
+    };
+    let objects = match &mut value {
+        Value::Object(object) => vec![object],
+        Value::Array(array) => array.iter_mut().filter_map(Value::as_object_mut).collect(),
+        _ => return line,
+    };
+    let mut pango = objects
+        .into_iter()
+        .filter(|o| o.get("markup").and_then(Value::as_str) == Some("pango"))
+        .peekable();
+    if pango.peek().is_none() {
+        return line;
+    }
+    for object in pango {
+        for key in ["full_text", "short_text"] {
+            if let Some(Value::String(text)) = object.get_mut(key) {
+                *text = text::check_markup(mem::take(text));
+            }
+        }
+    }
+    value.to_string()
+}
+
+/// The objects of a status line, either one object or an array of them,
+/// which may start with the comma separating it from the previous line.
+fn objects(line: &str) -> Option<Vec<Map<String, Value>>> {
+    match serde_json::from_str(line.trim().trim_start_matches(',')).ok()? {
+        Value::Object(object) => Some(vec![object]),
+        Value::Array(array) => array
+            .into_iter()
+            .map(|v| match v {
+                Value::Object(object) => Some(object),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Whether the last object of a status line allows a separator after it.
+pub fn separator_after(line: &str) -> bool {
+    objects(line)
+        .and_then(|objects| objects.last().map(allows_separator))
+        .unwrap_or(true)
+}
+
+fn allows_separator(object: &Map<String, Value>) -> bool {
+    object.get("separator") != Some(&Value::Bool(false))
+}
+
+/// The spans of a status line, styled on top of `attr`, or `None` if it isn't
+/// one. Objects use their `short_text` instead of their `full_text` if
+/// `short` is set, and `separator` goes between those that allow it.
+pub fn to_texts(
+    line: &str,
+    attr: &Attributes,
+    short: bool,
+    separator: Option<&Text>,
+) -> Option<Vec<Text>> {
+    let mut texts = vec![];
+    let mut separated = false;
+    for object in objects(line)? {
+        let string = |key| object.get(key).and_then(Value::as_str);
+        let full_text = match string("full_text") {
+            Some(full_text) => full_text,
+            None => continue,
+        };
+        if let (Some(separator), true) = (separator, separated) {
+            texts.push(separator.clone());
+        }
+        separated = allows_separator(&object);
+        let color = |key| string(key).and_then(|c| Color::from_str(c).ok());
+        let mut attr = attr.clone();
+        if let Some(color) = color("color") {
+            attr.fg_color = color;
+        }
+        if let Some(background) = color("background") {
+            attr.bg_color = Some(background);
+        }
+        // Borders are drawn as lines above and below the text.
+        if let Some(border) = color("border") {
+            attr.line_color = Some(border);
+            attr.underline = true;
+            attr.overline = true;
+        }
+        attr.min_width = match object.get("min_width") {
+            Some(Value::Number(width)) => width.as_f64().map(MinWidth::Pixels),
+            Some(Value::String(text)) => Some(MinWidth::Text(text.clone())),
+            _ => None,
+        };
+        attr.align = match string("align") {
+            Some("center") => Align::Center,
+            Some("right") => Align::Right,
+            _ => Align::Left,
+        };
+        if object.get("urgent") == Some(&Value::Bool(true)) {
+            attr.fg_color = Color::white();
+            attr.bg_color = Some(Color::red());
+        }
+        let text = match string("short_text") {
+            Some(short_text) if short => short_text,
+            _ => full_text,
+        };
+        let markup = string("markup") == Some("pango");
+        texts.push(Text {
+            attr,
+            text: text.to_string(),
+            actions: Default::default(),
+            markup,
+        });
+    }
+    Some(texts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn separator() -> Text {
+        Text {
+            attr: Attributes::default(),
+            text: "|".into(),
+            actions: Default::default(),
+            markup: false,
+        }
+    }
+
+    #[test]
+    fn urgent_i3blocks_output() {
+        let line = from_i3blocks("full\nshort\n#00ff00\n", true);
+        let texts = to_texts(&line, &Attributes::default(), false, None).unwrap();
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].text, "full");
+        assert_eq!(texts[0].attr.fg_color, Color::white());
+        assert_eq!(texts[0].attr.bg_color, Some(Color::red()));
+        let texts = to_texts(&line, &Attributes::default(), true, None).unwrap();
+        assert_eq!(texts[0].text, "short");
+    }
+
+    #[test]
+    fn empty_i3blocks_output_hides_the_block() {
+        assert_eq!(from_i3blocks("", false), "");
+        assert_eq!(from_i3blocks("\nshort\n", false), "");
+    }
+
+    #[test]
+    fn separators() {
+        let line = r#"[{"full_text":"a"},{"full_text":"b","separator":false},{"full_text":"c"}]"#;
+        let texts = to_texts(line, &Attributes::default(), false, Some(&separator())).unwrap();
+        let texts = texts.iter().map(|t| t.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, ["a", "|", "b", "c"]);
+        assert!(separator_after(line));
+        assert!(!separator_after(
+            r#",[{"full_text":"a","separator":false}]"#
+        ));
+    }
+
+    #[test]
+    fn framing() {
+        assert!(is_framing(r#"{"version":1,"click_events":true}"#));
+        assert!(is_framing("["));
+        assert!(!is_framing(r#"[{"full_text":"a"}]"#));
+    }
+}
@@ -0,0 +1,237 @@
+use super::{
+    error::{Diagnostic, Diagnostics, ParseError},
+    section::{Kind, Section},
+};
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString, OsStr},
+    fs, mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// A config and every file it includes, read up front so that sections can
+/// borrow from them.
+pub struct Files<'a> {
+    path: &'a Path,
+    source: &'a str,
+    /// The included files by their canonical path, with the path they were
+    /// included as.
+    included: HashMap<PathBuf, (PathBuf, Result<String, String>)>,
+}
+
+impl<'a> Files<'a> {
+    /// Reads every file included by `source`, the contents of the file at
+    /// `path`, and by the files it includes.
+    pub fn load(path: &'a Path, source: &'a str) -> Self {
+        let mut files = Self {
+            path,
+            source,
+            included: HashMap::new(),
+        };
+        files.read_includes(path, source);
+        files
+    }
+
+    fn read_includes(&mut self, path: &Path, source: &str) {
+        let included = match file_sections(source, path) {
+            Ok(sections) => sections
+                .iter()
+                .filter(|s| matches!(s.kind, Kind::Include))
+                .flat_map(|s| &s.entries)
+                .filter(|e| e.key == "include")
+                .filter_map(|e| expand(path, &e.value).ok())
+                .flatten()
+                .collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        for path in included {
+            let key = canonical(&path);
+            if key == canonical(self.path) || self.included.contains_key(&key) {
+                continue;
+            }
+            // Inserted before reading so that cycles stop here.
+            self.included
+                .insert(key.clone(), (path.clone(), Err(String::new())));
+            let source = fs::read_to_string(&path).map_err(|e| e.to_string());
+            if let Ok(source) = &source {
+                self.read_includes(&path, source);
+            }
+            self.included.insert(key, (path, source));
+        }
+    }
+
+    /// The path of the config and of every file it includes, even those that
+    /// couldn't be read.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.path).chain(self.included.values().map(|(path, _)| path.as_path()))
+    }
+
+    /// The path and contents of the file `error` is in.
+    pub fn locate(&self, error: &ParseError<'_>) -> (&Path, &str) {
+        self.included
+            .values()
+            .filter_map(|(path, source)| Some((path.as_path(), source.as_deref().ok()?)))
+            .find(|(_, source)| error.is_in(source))
+            .unwrap_or((self.path, self.source))
+    }
+}
+
+/// The sections of the config, with every include replaced by the sections of
+/// the files it includes. Syntax errors in included TOML files can't be
+/// located in sections, so they are returned apart.
+pub fn sections<'a>(
+    files: &'a Files<'a>,
+) -> Result<(Vec<Section<'a>>, Vec<Diagnostic>), Diagnostics> {
+    let root = file_sections(files.source, files.path)?;
+    let mut sections = vec![];
+    let mut diagnostics = vec![];
+    splice(
+        files,
+        (files.path, root),
+        &mut vec![canonical(files.path)],
+        (&mut sections, &mut diagnostics),
+    );
+    Ok((sections, diagnostics))
+}
+
+/// Adds the sections of the file at `path` to `sections`, and those of the
+/// files it includes where it includes them. `including` holds the files
+/// being spliced, to catch cycles.
+fn splice<'a>(
+    files: &'a Files<'a>,
+    (path, file): (&Path, Vec<Section<'a>>),
+    including: &mut Vec<PathBuf>,
+    (sections, diagnostics): (&mut Vec<Section<'a>>, &mut Vec<Diagnostic>),
+) {
+    for mut section in file {
+        if !matches!(section.kind, Kind::Include) {
+            sections.push(section);
+            continue;
+        }
+        for entry in mem::take(&mut section.entries) {
+            if entry.key != "include" {
+                let error = ParseError::unknown_option(&entry.key, &["include"]);
+                section
+                    .errors
+                    .push(error.relocate(&[(&entry.key, entry.key_span)]));
+                continue;
+            }
+            let paths = match expand(path, &entry.value) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    section.errors.push((entry.value_span, e).into());
+                    continue;
+                }
+            };
+            for included in paths {
+                let key = canonical(&included);
+                if including.contains(&key) {
+                    section.errors.push(
+                        (
+                            entry.value_span,
+                            format!("'{}' is already being included", included.display()),
+                        )
+                            .into(),
+                    );
+                    continue;
+                }
+                match files.included.get(&key) {
+                    Some((path, Ok(source))) => match file_sections(source, path) {
+                        Ok(file) => {
+                            including.push(key);
+                            splice(files, (path, file), including, (sections, diagnostics));
+                            including.pop();
+                        }
+                        Err(e) => diagnostics.extend(e.0),
+                    },
+                    Some((_, Err(e))) => section.errors.push(
+                        (
+                            entry.value_span,
+                            format!("Couldn't read '{}': {}", included.display(), e),
+                        )
+                            .into(),
+                    ),
+                    None => section.errors.push(
+                        (
+                            entry.value_span,
+                            format!("Couldn't read '{}'", included.display()),
+                        )
+                            .into(),
+                    ),
+                }
+            }
+        }
+        sections.push(section);
+    }
+}
+
+/// The sections of one file, with the includes of its global section split
+/// out so that options after them override the included ones.
+fn file_sections<'a>(source: &'a str, path: &Path) -> Result<Vec<Section<'a>>, Diagnostics> {
+    let sections = if super::is_toml(path) {
+        super::toml_config::sections(source, path)?
+    } else {
+        super::lemonrc_sections(source)
+    };
+    let mut split = vec![];
+    for mut section in sections {
+        if !matches!(section.kind, Kind::Global) {
+            split.push(section);
+            continue;
+        }
+        let header = section.header;
+        let entries = mem::take(&mut section.entries);
+        split.push(section);
+        for entry in entries {
+            let kind = if entry.key == "include" {
+                Kind::Include
+            } else {
+                Kind::Global
+            };
+            match split.last_mut() {
+                Some(last) if mem::discriminant(&last.kind) == mem::discriminant(&kind) => {
+                    last.entries.push(entry)
+                }
+                _ => split.push(Section {
+                    kind,
+                    header,
+                    entries: vec![entry],
+                    variables: vec![],
+                    errors: vec![],
+                }),
+            }
+        }
+    }
+    Ok(split)
+}
+
+/// The files `pattern` matches, relative to the directory of the file at
+/// `path`. Patterns without wildcards are kept as they are, even if the file
+/// doesn't exist, so that it can be reported.
+fn expand(path: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let pattern = path.parent().unwrap_or(Path::new("")).join(pattern);
+    let bytes = pattern.as_os_str().as_bytes();
+    if !bytes.iter().any(|b| b"*?[".contains(b)) {
+        return Ok(vec![pattern]);
+    }
+    let c_pattern = CString::new(bytes).map_err(|e| e.to_string())?;
+    let mut glob: libc::glob_t = unsafe { mem::zeroed() };
+    let paths = match unsafe { libc::glob(c_pattern.as_ptr(), 0, None, &mut glob) } {
+        0 => Ok((0..glob.gl_pathc)
+            .map(|i| {
+                let path = unsafe { CStr::from_ptr(*glob.gl_pathv.add(i)) };
+                PathBuf::from(OsStr::from_bytes(path.to_bytes()))
+            })
+            .collect()),
+        libc::GLOB_NOMATCH => Ok(vec![]),
+        _ => Err(format!("Couldn't expand '{}'", pattern.display())),
+    };
+    unsafe { libc::globfree(&mut glob) };
+    paths
+}
+
+/// The path without links or `..`, to tell if two paths are the same file.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
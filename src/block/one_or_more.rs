@@ -31,42 +31,36 @@ impl<T> IndexMut<usize> for OneOrMore<T> {
 }
 
 impl<T> OneOrMore<T> {
-    pub fn push(&mut self, t: T) {
-        match std::mem::replace(self, OneOrMore::More(vec![])) {
-            Self::One(o) => *self = Self::More(vec![o, t]),
-            Self::More(mut m) => {
-                m.push(t);
-                *self = OneOrMore::More(m)
-            }
-        }
-    }
-
-    pub fn len(&self) -> usize {
-        match self {
-            Self::One(_) => 1,
-            Self::More(m) => m.len(),
-        }
+    /// Grows or shrinks to hold `len` elements, filling with `T::default()`.
+    pub fn resize(&mut self, len: usize)
+    where
+        T: Default,
+    {
+        let mut v = match std::mem::replace(self, OneOrMore::More(vec![])) {
+            Self::One(o) => vec![o],
+            Self::More(m) => m,
+        };
+        v.resize_with(len.max(1), T::default);
+        *self = if v.len() == 1 {
+            Self::One(v.pop().unwrap())
+        } else {
+            Self::More(v)
+        };
     }
 
-    pub fn iter(&self) -> Iter<T> {
+    /// Like indexing, but returns `None` if there's more than one element and
+    /// `i` is out of bounds.
+    pub fn get_mut(&mut self, i: usize) -> Option<&mut T> {
         match self {
-            Self::One(t) => Iter::One(Some(t)),
-            Self::More(m) => Iter::More(m.iter()),
+            Self::One(t) => Some(t),
+            Self::More(m) => m.get_mut(i),
         }
     }
-}
-
-pub enum Iter<'a, T> {
-    One(Option<&'a T>),
-    More(std::slice::Iter<'a, T>),
-}
 
-impl<'a, T> Iterator for Iter<'a, T> {
-    type Item = &'a T;
-    fn next(&mut self) -> Option<Self::Item> {
+    pub fn len(&self) -> usize {
         match self {
-            Self::One(t) => t.take(),
-            Self::More(m) => m.next(),
+            Self::One(_) => 1,
+            Self::More(m) => m.len(),
         }
     }
 }
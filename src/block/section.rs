@@ -0,0 +1,368 @@
+use super::{
+    error::{Diagnostic, Diagnostics, ParseError},
+    include::Files,
+    Alignment, BlockBuilder, Config, GlobalConfig,
+};
+use std::{borrow::Cow, collections::HashMap, env};
+
+/// Options whose values are shell commands, in which `${...}` that isn't one
+/// of our variables is left for the shell.
+const SHELL_OPTIONS: &[&str] = &[
+    "command",
+    "cmd",
+    "persistent",
+    "only-if",
+    "left-click",
+    "middle-click",
+    "right-click",
+    "scroll-up",
+    "scroll-down",
+];
+
+/// A part of a config, in a format independent way.
+pub struct Section<'a> {
+    pub kind: Kind<'a>,
+    /// Where the section starts, for errors about the whole section.
+    pub header: &'a str,
+    pub entries: Vec<Entry<'a>>,
+    /// Variables `set` in the global section, with their names as keys.
+    pub variables: Vec<Entry<'a>>,
+    /// Options that couldn't even be read, like lines without a `:`.
+    pub errors: Vec<ParseError<'a>>,
+}
+
+pub enum Kind<'a> {
+    Global,
+    Block,
+    /// Options blocks can `inherit` instead of repeating them.
+    Style(&'a str),
+    /// Other files to read the sections of in its place.
+    Include,
+}
+
+/// One option of a section.
+pub struct Entry<'a> {
+    pub key: Cow<'a, str>,
+    pub value: Cow<'a, str>,
+    /// Where the key and value were written, which can differ from the key
+    /// and value themselves if they were quoted or escaped.
+    pub key_span: &'a str,
+    pub value_span: &'a str,
+}
+
+impl<'a> Entry<'a> {
+    /// An entry whose key and value are written as they are.
+    pub fn new(key: &'a str, value: &'a str) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+            key_span: key,
+            value_span: value,
+        }
+    }
+
+    /// Runs `set` on the key and the value, with its variables expanded,
+    /// pointing any error at where they were written.
+    fn apply(
+        &self,
+        variables: &Variables,
+        set: impl for<'v> FnOnce(&'v str, &'v str) -> Result<(), ParseError<'v>>,
+    ) -> Result<(), ParseError<'a>> {
+        let relocate = |e: ParseError<'_>| {
+            e.relocate(&[(&self.key, self.key_span), (&self.value, self.value_span)])
+        };
+        let shell = SHELL_OPTIONS.contains(&self.key.as_ref());
+        let value = variables.expand(&self.value, shell).map_err(relocate)?;
+        set(&self.key, &value)
+            .map_err(|e| e.relocate(&[(&self.key, self.key_span), (&value, self.value_span)]))
+    }
+}
+
+/// Values for `${name}` in options: the ones `set` in the global section,
+/// then the environment's.
+#[derive(Default)]
+struct Variables(HashMap<String, String>);
+
+impl Variables {
+    /// Defines the variable `entry`, which can use the ones defined before
+    /// it.
+    fn set<'a>(&mut self, entry: &Entry<'a>) -> Result<(), ParseError<'a>> {
+        let name = &entry.key;
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err((
+                entry.key_span,
+                "Invalid variable name, expected letters, digits and _",
+            )
+                .into());
+        }
+        let value = self
+            .expand(&entry.value, false)
+            .map_err(|e| e.relocate(&[(&entry.value, entry.value_span)]))?;
+        self.0.insert(name.to_string(), value.into_owned());
+        Ok(())
+    }
+
+    /// Replaces every `${name}` in `value`. `\${` is kept as a literal `${`,
+    /// and so is anything that can't be a variable name, like `${1:-x}`. In
+    /// `shell` commands, unknown names are left for the shell too.
+    fn expand<'v>(&self, value: &'v str, shell: bool) -> Result<Cow<'v, str>, ParseError<'v>> {
+        if !value.contains("${") {
+            return Ok(value.into());
+        }
+        let mut expanded = String::new();
+        let mut rest = value;
+        while let Some(start) = rest.find("${") {
+            if rest[..start].ends_with('\\') {
+                expanded.push_str(&rest[..start - 1]);
+                expanded.push_str("${");
+                rest = &rest[start + 2..];
+                continue;
+            }
+            expanded.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end + 1,
+                None if shell => break,
+                None => return Err((&rest[start..], "Unclosed '${'").into()),
+            };
+            let name = &rest[start + 2..end - 1];
+            let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            match self.0.get(name).cloned().or_else(|| env::var(name).ok()) {
+                Some(v) if is_name => expanded.push_str(&v),
+                _ if !is_name || shell => expanded.push_str(&rest[start..end]),
+                _ => {
+                    let known = self.0.keys().map(String::as_str).collect::<Vec<_>>();
+                    return Err(
+                        ParseError::unknown("variable", name, &known).with_span(&rest[start..end])
+                    );
+                }
+            }
+            rest = &rest[end..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded.into())
+    }
+}
+
+/// Builds the config out of its sections, the parsed contents of `files`,
+/// collecting every error.
+pub fn resolve(
+    sections: Vec<Section<'_>>,
+    files: &Files,
+    mut errors: Vec<Diagnostic>,
+) -> Result<(GlobalConfig, Config), Diagnostics> {
+    let mut located = |e, section: Option<String>| {
+        let (path, source) = files.locate(&e);
+        errors.push(Diagnostic::new(e, path, source, section));
+    };
+    let mut styles = HashMap::new();
+    for section in &sections {
+        if let Kind::Style(name) = section.kind {
+            if styles.insert(name, section).is_some() {
+                located(
+                    (section.header, format!("Style '{}' is defined twice", name)).into(),
+                    None,
+                );
+            }
+        }
+    }
+    let mut variables = Variables::default();
+    for section in &sections {
+        for e in section
+            .variables
+            .iter()
+            .filter_map(|v| variables.set(v).err())
+        {
+            located(e, None);
+        }
+    }
+    let mut global_config = GlobalConfig::default();
+    let mut blocks = HashMap::<Alignment, Vec<_>>::with_capacity(3);
+    let mut n_block = 0;
+    let mut names = HashMap::new();
+    for section in &sections {
+        let (name, section_errors) = match section.kind {
+            // Includes are already replaced, only their errors are left.
+            Kind::Include => (None, vec![]),
+            Kind::Global => {
+                let errors = section
+                    .entries
+                    .iter()
+                    .filter_map(|e| e.apply(&variables, |k, v| global_config.option(k, v)).err())
+                    .collect::<Vec<_>>();
+                (None, errors)
+            }
+            // Styles are checked on their own so their errors are only
+            // reported once, instead of once per block inheriting them.
+            Kind::Style(name) => {
+                let (mut errors, mut cycles) = (vec![], vec![]);
+                inherit(
+                    section,
+                    (&styles, &variables),
+                    &mut BlockBuilder::default(),
+                    &mut vec![name],
+                    (&mut errors, &mut cycles),
+                );
+                errors.append(&mut cycles);
+                (Some(format!("style '{}'", name)), errors)
+            }
+            Kind::Block => {
+                n_block += 1;
+                let mut block_b = BlockBuilder::default();
+                let mut errors = vec![];
+                inherit(
+                    section,
+                    (&styles, &variables),
+                    &mut block_b,
+                    &mut vec![],
+                    (&mut errors, &mut vec![]),
+                );
+                let label = match &block_b.name {
+                    Some(name) => format!("block '{}'", name),
+                    None => format!("block {}", n_block),
+                };
+                // Blocks with invalid options are incomplete, building them
+                // would only add misleading errors.
+                if errors.is_empty() && section.errors.is_empty() {
+                    match block_b.build(n_block) {
+                        // Blocks whose conditions fail are left out, as if
+                        // they weren't in the config. Only shown blocks need
+                        // unique names, so hosts can each define theirs.
+                        Ok(block) if block.is_shown() => {
+                            match names.insert(block.name().to_string(), n_block) {
+                                Some(first) => {
+                                    let error = format!(
+                                        "Block name '{}' is already used by block {}",
+                                        block.name(),
+                                        first
+                                    );
+                                    errors.push((section.header, error).into());
+                                }
+                                None => blocks.entry(block.alignment).or_default().push(block),
+                            }
+                        }
+                        Ok(_) => (),
+                        Err(e) => errors.push((section.header, e).into()),
+                    }
+                }
+                (Some(label), errors)
+            }
+        };
+        for e in section.errors.iter().cloned().chain(section_errors) {
+            located(e, name.clone());
+        }
+    }
+    if errors.is_empty() {
+        Ok((global_config, blocks))
+    } else {
+        Err(Diagnostics(errors))
+    }
+}
+
+/// Applies the options of `section` to `block_b`, after the ones of the
+/// styles it inherits from. `inheriting` holds the styles being applied, to
+/// catch cycles.
+///
+/// Only errors in `section` itself are added to `errors`, and cycles back to
+/// the first style in `inheriting` to `cycles`.
+fn inherit<'a>(
+    section: &Section<'a>,
+    (styles, variables): (&HashMap<&'a str, &Section<'a>>, &Variables),
+    block_b: &mut BlockBuilder,
+    inheriting: &mut Vec<&'a str>,
+    (errors, cycles): (&mut Vec<ParseError<'a>>, &mut Vec<ParseError<'a>>),
+) {
+    let (parents, options) = section
+        .entries
+        .iter()
+        .partition::<Vec<_>, _>(|e| e.key == "inherit");
+    for entry in parents {
+        for name in entry.value.split(',').map(str::trim) {
+            let error = match styles.get_key_value(name) {
+                // Other cycles are reported when checking the style they
+                // loop back to.
+                Some((&name, _)) if inheriting.contains(&name) => {
+                    if inheriting.first() == Some(&name) {
+                        let error = (name, format!("Style '{}' inherits from itself", name));
+                        cycles.push(
+                            ParseError::from(error).relocate(&[(&entry.value, entry.value_span)]),
+                        );
+                    }
+                    continue;
+                }
+                Some((&name, style)) => {
+                    inheriting.push(name);
+                    inherit(
+                        style,
+                        (styles, variables),
+                        block_b,
+                        inheriting,
+                        (&mut vec![], cycles),
+                    );
+                    inheriting.pop();
+                    continue;
+                }
+                None => {
+                    let known = styles.keys().copied().collect::<Vec<_>>();
+                    ParseError::unknown("style", name, &known)
+                }
+            };
+            errors.push(error.relocate(&[(&entry.value, entry.value_span)]));
+        }
+    }
+    errors.extend(
+        options
+            .into_iter()
+            .filter_map(|e| e.apply(variables, |k, v| block_b.option(k, v)).err()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn variables() -> Variables {
+        Variables(HashMap::from([("greet".to_string(), "hi".to_string())]))
+    }
+
+    #[test]
+    fn expands_variables() {
+        let expanded = variables().expand("${greet} there", false).unwrap();
+        assert_eq!(expanded, "hi there");
+    }
+
+    #[test]
+    fn escaped_variables_are_literal() {
+        let expanded = variables().expand("echo \\${greet}", false).unwrap();
+        assert_eq!(expanded, "echo ${greet}");
+    }
+
+    #[test]
+    fn shell_parameters_are_kept() {
+        for shell in [false, true] {
+            let expanded = variables().expand("echo ${1:-x}", shell).unwrap();
+            assert_eq!(expanded, "echo ${1:-x}");
+        }
+    }
+
+    #[test]
+    fn unknown_variables() {
+        let expanded = variables().expand("f=1; echo ${f}", true).unwrap();
+        assert_eq!(expanded, "f=1; echo ${f}");
+        assert!(variables().expand("${f}", false).is_err());
+        assert!(variables().expand("${greet", false).is_err());
+        assert_eq!(variables().expand("${greet", true).unwrap(), "${greet");
+    }
+
+    #[test]
+    fn commands_keep_unknown_variables() {
+        let config = ">\nalignment: left\ncmd: f=1; echo ${f} ${1:-x}\n";
+        assert!(super::super::parse(config, Path::new("lemonrc")).is_ok());
+        let config = ">\nalignment: left\nstatic: ${f}\n";
+        let errors = super::super::parse(config, Path::new("lemonrc"))
+            .err()
+            .unwrap();
+        assert_eq!(errors.0.len(), 1);
+    }
+}
@@ -1,16 +1,428 @@
-// use async_std::{task, stream::{self, Stream}};
-// use futures_util::StreamExt;
-// use super::{Block,Config};
-// use crate::text::Text;
-
-// pub fn make_block_stream(config: Config) -> impl Stream<Item = Text> {
-//     stream::empty()
-// }
-
-// pub fn block_to_stream(mut block: Block) -> impl Stream<Item = Option<Text>> {
-//     stream::repeat(()).then(move |_| async {
-//         task::sleep(block.interval).await;
-//         block.update();
-//         block.to_text(0)
-//     })
-// }
+use super::{i3bar, Alignment, Block, Config, Content, Format};
+use crate::event::Event;
+use async_std::{
+    future,
+    sync::{channel, Sender},
+    task,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, BufRead, BufReader, Write},
+    os::unix::process::{CommandExt, ExitStatusExt},
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// The exit code of i3blocks commands whose output is urgent.
+const URGENT: i32 = 33;
+/// How many of the last lines a failed command wrote to stderr are kept.
+const STDERR_TAIL: usize = 5;
+
+/// A new output from the task `task`, or why its command failed. It's only
+/// applied to the task's block if the task is still running once the update
+/// is handled, wherever the block is then.
+pub struct Update {
+    pub task: TaskId,
+    pub monitor: usize,
+    pub output: Result<String, Failure>,
+}
+
+/// Tells tasks apart, as their blocks move when they're kept across a
+/// reload.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Why a block's command failed, logged by the block when it fails
+/// differently than before.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    /// A few words to show in the bar, like `exit 1`.
+    pub summary: String,
+    /// What happened, with the end of what the command wrote to stderr.
+    pub message: String,
+}
+
+impl Failure {
+    fn new(summary: impl Into<String>, message: String) -> Self {
+        Self {
+            summary: summary.into(),
+            message,
+        }
+    }
+
+    /// `cmd` exited with `status`, after writing `stderr`.
+    fn exited(cmd: &str, status: ExitStatus, stderr: &str) -> Self {
+        let summary = match (status.code(), status.signal()) {
+            (Some(code), _) => format!("exit {}", code),
+            (None, Some(signal)) => format!("signal {}", signal),
+            (None, None) => "failed".into(),
+        };
+        let mut message = format!("'{}' failed with {}", cmd, status);
+        let lines = stderr.trim_end().lines().collect::<Vec<_>>();
+        for line in &lines[lines.len().saturating_sub(STDERR_TAIL)..] {
+            message.push('\n');
+            message.push_str(line);
+        }
+        Self::new(summary, message)
+    }
+}
+
+/// Handles to the tasks running a config's blocks. Dropping a handle stops
+/// its task.
+#[derive(Default)]
+pub struct Tasks(HashMap<(Alignment, usize), Task>);
+
+struct Task {
+    id: TaskId,
+    /// Set once the task is dropped, so it stops sending updates.
+    stopped: Arc<AtomicBool>,
+    handle: Handle,
+}
+
+enum Handle {
+    /// Reruns the command when sent to.
+    Cmd(Sender<()>),
+    /// One process per monitor, only held on to so they're killed on drop.
+    #[allow(dead_code)]
+    Persistent(Vec<Process>),
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Tasks {
+    /// Where the block of the task `id` currently is, or `None` if the task
+    /// was stopped, in which case its late updates are dropped.
+    pub fn position(&self, id: TaskId) -> Option<(Alignment, usize)> {
+        self.0
+            .iter()
+            .find(|(_, task)| task.id == id)
+            .map(|(&position, _)| position)
+    }
+
+    /// Makes the block at `index` of the `alignment` section rerun now
+    /// instead of waiting for its interval, returning whether it's a `cmd`
+    /// block that can be.
+    pub async fn refresh(&self, alignment: Alignment, index: usize) -> bool {
+        match self.0.get(&(alignment, index)) {
+            Some(Task {
+                handle: Handle::Cmd(trigger),
+                ..
+            }) => {
+                // A pending trigger already guarantees a rerun.
+                if !trigger.is_full() {
+                    trigger.send(()).await;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Starts producing output for `block`, at `index` of the `alignment`
+    /// section.
+    fn spawn(&mut self, alignment: Alignment, index: usize, block: &Block, sender: &Sender<Event>) {
+        let (id, stopped) = (TaskId::new(), Arc::new(AtomicBool::new(false)));
+        let update = {
+            let stopped = Arc::clone(&stopped);
+            move |monitor, output| {
+                if stopped.load(Ordering::SeqCst) {
+                    return None;
+                }
+                Some(Event::Update(Update {
+                    task: id,
+                    monitor,
+                    output,
+                }))
+            }
+        };
+        let handle = match &block.content {
+            Content::Static(_) => return,
+            Content::Cmd { cmd, last_run } => {
+                let (cmd, name, format) = (cmd.clone(), block.name.clone(), block.format);
+                let only_if = block.only_if.clone().filter(|_| block.recheck);
+                let n_monitor = last_run.len();
+                let interval = block.interval;
+                let sender = sender.clone();
+                let (trigger, triggered) = channel(1);
+                task::spawn(async move {
+                    loop {
+                        for monitor in 0..n_monitor {
+                            let (cmd, name) = (cmd.clone(), name.clone());
+                            let only_if = only_if.clone();
+                            // Blocks whose condition fails are hidden by
+                            // having no output.
+                            let output = task::spawn_blocking(move || match only_if {
+                                Some(c) if !super::succeeds(&c, &name, Some(monitor)) => {
+                                    Ok(String::new())
+                                }
+                                _ => run_command(&cmd, &name, monitor, format),
+                            })
+                            .await;
+                            match update(monitor, output) {
+                                Some(event) => sender.send(event).await,
+                                None => return,
+                            }
+                        }
+                        if let Ok(None) = future::timeout(interval, triggered.recv()).await {
+                            break;
+                        }
+                    }
+                });
+                Handle::Cmd(trigger)
+            }
+            Content::Persistent { cmd, last_run } => Handle::Persistent(
+                (0..last_run.len())
+                    .map(|monitor| {
+                        let process = Process::default();
+                        let (cmd, name, format) = (cmd.clone(), block.name.clone(), block.format);
+                        let stopped = Arc::clone(&process.stopped);
+                        let pid = Arc::clone(&process.pid);
+                        let sender = sender.clone();
+                        let update = update.clone();
+                        thread::spawn(move || {
+                            run_persistent((&cmd, &name), monitor, &stopped, &pid, |output| {
+                                // Every line replaces the full text.
+                                let output = output.map(|text| match format {
+                                    Format::I3blocks => i3bar::from_i3blocks(&text, false),
+                                    _ => text,
+                                });
+                                if let Some(event) = update(monitor, output) {
+                                    task::block_on(sender.send(event))
+                                }
+                            })
+                        });
+                        process
+                    })
+                    .collect(),
+            ),
+        };
+        self.0.insert(
+            (alignment, index),
+            Task {
+                id,
+                stopped,
+                handle,
+            },
+        );
+    }
+}
+
+/// A running `persistent` command, which is killed when this is dropped.
+#[derive(Default)]
+struct Process {
+    stopped: Arc<AtomicBool>,
+    pid: Arc<AtomicU32>,
+}
+
+impl Process {
+    fn kill(pid: &AtomicU32) {
+        // Commands run in their own process group so the whole pipeline is killed.
+        match pid.load(Ordering::SeqCst) {
+            0 => (),
+            pid => unsafe {
+                libc::kill(-(pid as libc::pid_t), libc::SIGTERM);
+            },
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        Self::kill(&self.pid);
+    }
+}
+
+/// Starts producing output for every block in `config`.
+///
+/// `cmd` blocks get a task that reruns them every `interval` or when
+/// triggered, and `persistent` blocks get one reader thread per monitor.
+/// Static blocks never change so nothing is spawned for them.
+pub fn spawn_blocks(config: &Config, sender: Sender<Event>) -> Tasks {
+    let mut tasks = Tasks::default();
+    for (&alignment, blocks) in config {
+        for (index, block) in blocks.iter().enumerate() {
+            tasks.spawn(alignment, index, block, &sender);
+        }
+    }
+    tasks
+}
+
+/// Starts the blocks of a reloaded config, `new`, keeping the tasks of the
+/// blocks in `old` that run the same command so they aren't restarted.
+/// Kept blocks also keep their last output.
+pub fn respawn_blocks(
+    mut tasks: Tasks,
+    mut old: Config,
+    new: &mut Config,
+    sender: Sender<Event>,
+) -> Tasks {
+    let mut respawned = Tasks::default();
+    for (&alignment, blocks) in new.iter_mut() {
+        for (index, block) in blocks.iter_mut().enumerate() {
+            let kept = old
+                .iter_mut()
+                .flat_map(|(&a, blocks)| {
+                    blocks.iter_mut().enumerate().map(move |(i, b)| ((a, i), b))
+                })
+                .find(|(key, old)| tasks.0.contains_key(key) && old.runs_like(block));
+            match kept {
+                Some((key, old)) => {
+                    let task = tasks.0.remove(&key).expect("the task was just found");
+                    block.keep_output(old);
+                    respawned.0.insert((alignment, index), task);
+                }
+                None => respawned.spawn(alignment, index, block, &sender),
+            }
+        }
+    }
+    respawned
+}
+
+/// Runs `cmd` once, returning the first line of its output, or all of it
+/// as a status line for i3blocks commands. What it writes to stderr is
+/// passed on, unless it fails, in which case it's part of the failure.
+fn run_command(cmd: &str, name: &str, monitor: usize, format: Format) -> Result<String, Failure> {
+    let output = Command::new("sh")
+        .args(["-c", cmd])
+        .env("MONITOR", monitor.to_string())
+        .env("BLOCK_NAME", name)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|c| c.wait_with_output())
+        .map_err(|e| Failure::new("not run", format!("'{}' couldn't be run: {}", cmd, e)))?;
+    let urgent = format == Format::I3blocks && output.status.code() == Some(URGENT);
+    if !output.status.success() && !urgent {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Failure::exited(cmd, output.status, &stderr));
+    }
+    let _ = io::stderr().write_all(&output.stderr);
+    let mut stdout = String::from_utf8(output.stdout).map_err(|e| {
+        let message = format!("'{}' printed invalid UTF-8: {}", cmd, e);
+        Failure::new("invalid output", message)
+    })?;
+    Ok(match format {
+        Format::I3blocks => i3bar::from_i3blocks(&stdout, urgent),
+        _ => {
+            if let Some(i) = stdout.find('\n') {
+                stdout.truncate(i);
+            }
+            stdout
+        }
+    })
+}
+
+/// Runs `cmd` until `stopped` is set, calling `on_output` with every line it
+/// prints, or with the failure if it exits unsuccessfully or can't be run.
+/// If the command exits it's restarted, waiting exponentially longer
+/// each time it dies quickly. The running process' id is kept in `pid`.
+fn run_persistent(
+    (cmd, name): (&str, &str),
+    monitor: usize,
+    stopped: &AtomicBool,
+    pid: &AtomicU32,
+    mut on_output: impl FnMut(Result<String, Failure>),
+) {
+    let mut backoff = MIN_BACKOFF;
+    while !stopped.load(Ordering::SeqCst) {
+        let started = Instant::now();
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", cmd])
+            .env("MONITOR", monitor.to_string())
+            .env("BLOCK_NAME", name)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
+        // Being in its own group, the command would outlive the bar if it
+        // died without dropping it, e.g. when it's SIGKILLed.
+        let bar = std::process::id();
+        unsafe {
+            command.pre_exec(move || {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // The bar may have died before that was set. Errors here
+                // mustn't allocate, as other threads may hold the allocator.
+                if libc::getppid() as u32 != bar {
+                    return Err(io::Error::from_raw_os_error(libc::ESRCH));
+                }
+                Ok(())
+            })
+        };
+        match command.spawn() {
+            Ok(mut child) => {
+                pid.store(child.id(), Ordering::SeqCst);
+                // The process may have been stopped before its pid was known.
+                if stopped.load(Ordering::SeqCst) {
+                    Process::kill(pid);
+                }
+                // Its stderr is passed on as it comes, keeping the end in
+                // case it fails.
+                let stderr = child.stderr.take().map(|stderr| {
+                    thread::spawn(move || {
+                        let mut tail = VecDeque::with_capacity(STDERR_TAIL);
+                        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                            eprintln!("{}", line);
+                            if tail.len() == STDERR_TAIL {
+                                tail.pop_front();
+                            }
+                            tail.push_back(line);
+                        }
+                        Vec::from(tail).join("\n")
+                    })
+                });
+                if let Some(stdout) = child.stdout.take() {
+                    BufReader::new(stdout)
+                        .lines()
+                        .map_while(Result::ok)
+                        .take_while(|_| !stopped.load(Ordering::SeqCst))
+                        .for_each(|line| on_output(Ok(line)));
+                }
+                let status = child.wait();
+                pid.store(0, Ordering::SeqCst);
+                if stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                match status {
+                    Ok(status) if status.success() => {
+                        eprintln!("Block '{}': '{}' exited with {}", name, cmd, status)
+                    }
+                    Ok(status) => {
+                        let stderr = stderr.and_then(|t| t.join().ok()).unwrap_or_default();
+                        on_output(Err(Failure::exited(cmd, status, &stderr)))
+                    }
+                    Err(e) => eprintln!("Block '{}': '{}' couldn't be waited on: {}", name, cmd, e),
+                }
+            }
+            Err(e) => on_output(Err(Failure::new(
+                "not run",
+                format!("failed to spawn '{}': {}", cmd, e),
+            ))),
+        }
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = MIN_BACKOFF;
+        }
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
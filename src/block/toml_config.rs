@@ -0,0 +1,311 @@
+use super::{
+    error::{Diagnostic, Diagnostics, ParseError},
+    section::{Entry, Kind, Section},
+};
+use std::{borrow::Cow, path::Path};
+use toml::de::{DeTable, DeValue};
+
+const SECTIONS: &[&str] = &["global", "block", "style"];
+
+/// Splits a TOML config into its sections: the `[global]` table holds the
+/// global options, every `[[block]]` table defines a block and every
+/// `[style.<name>]` table a style, with the same option names as a lemonrc.
+///
+/// Strings can span several lines and an array sets an option once per item,
+/// e.g. to give several fonts.
+pub fn sections<'a>(config: &'a str, path: &Path) -> Result<Vec<Section<'a>>, Diagnostics> {
+    let table = match DeTable::parse(config) {
+        Ok(table) => table.into_inner(),
+        Err(e) => {
+            let span = e.span().map_or("", |s| &config[s]);
+            let error = ParseError::from((span, e.message().to_string()));
+            return Err(Diagnostics(vec![Diagnostic::new(
+                error, path, config, None,
+            )]));
+        }
+    };
+    let mut sections = vec![];
+    // Errors outside of any section are reported with the global ones.
+    let mut global = section(config, Kind::Global, &config[..0], None);
+    for (key, value) in table {
+        let span = &config[key.span()];
+        match (key.into_inner().as_ref(), value.into_inner()) {
+            ("global", DeValue::Table(table)) => {
+                global = section(config, Kind::Global, span, Some(table))
+            }
+            ("block", DeValue::Array(array)) => {
+                for block in array {
+                    let header = config[block.span()].lines().next().unwrap_or_default();
+                    sections.push(match block.into_inner() {
+                        // Tables that include other files aren't blocks.
+                        DeValue::Table(table) if table.keys().any(|k| k.get_ref() == "include") => {
+                            section(config, Kind::Include, header, Some(table))
+                        }
+                        DeValue::Table(table) => section(config, Kind::Block, header, Some(table)),
+                        _ => {
+                            let mut block = section(config, Kind::Block, header, None);
+                            block
+                                .errors
+                                .push((header, "Expected a [[block]] table").into());
+                            block
+                        }
+                    })
+                }
+            }
+            ("style", DeValue::Table(styles)) => {
+                for (name, style) in styles {
+                    // Style names are used where they're written, so they
+                    // can't have escapes.
+                    let name = config[name.span()].trim_matches(|c| c == '"' || c == '\'');
+                    match style.into_inner() {
+                        DeValue::Table(table) => {
+                            sections.push(section(config, Kind::Style(name), name, Some(table)))
+                        }
+                        _ => global
+                            .errors
+                            .push((name, "Expected a [style.<name>] table").into()),
+                    }
+                }
+            }
+            ("global", _) => global
+                .errors
+                .push((span, "Expected a [global] table").into()),
+            ("block", _) => global
+                .errors
+                .push((span, "Expected [[block]] tables").into()),
+            ("style", _) => global
+                .errors
+                .push((span, "Expected [style.<name>] tables").into()),
+            _ => global
+                .errors
+                .push(ParseError::unknown("section", span, SECTIONS)),
+        }
+    }
+    sections.insert(0, global);
+    Ok(sections)
+}
+
+/// Makes a section out of the options in `table`.
+fn section<'a>(
+    config: &'a str,
+    kind: Kind<'a>,
+    header: &'a str,
+    table: Option<DeTable<'a>>,
+) -> Section<'a> {
+    let mut section = Section {
+        kind,
+        header,
+        entries: vec![],
+        variables: vec![],
+        errors: vec![],
+    };
+    for (key, value) in table.into_iter().flatten() {
+        let key_span = &config[key.span()];
+        let key = key.into_inner();
+        let span = value.span();
+        if matches!(section.kind, Kind::Global) && key == "set" {
+            match value.into_inner() {
+                DeValue::Table(variables) => {
+                    for (name, value) in variables {
+                        let name_span = &config[name.span()];
+                        let value_span = &config[value.span()];
+                        match scalar(value_span, value.into_inner()) {
+                            Ok(value) => section.variables.push(Entry {
+                                key: name.into_inner(),
+                                value,
+                                key_span: name_span,
+                                value_span,
+                            }),
+                            Err(e) => section.errors.push(e),
+                        }
+                    }
+                }
+                _ => section
+                    .errors
+                    .push((&config[span], "Expected a [global.set] table of variables").into()),
+            }
+            continue;
+        }
+        let values = match value.into_inner() {
+            DeValue::Array(array) => array
+                .into_iter()
+                .map(|v| (v.span(), v.into_inner()))
+                .collect(),
+            value => vec![(span, value)],
+        };
+        for (span, value) in values {
+            let value_span = &config[span];
+            match scalar(value_span, value) {
+                Ok(value) => section.entries.push(Entry {
+                    key: key.clone(),
+                    value,
+                    key_span,
+                    value_span,
+                }),
+                Err(e) => section.errors.push(e),
+            }
+        }
+    }
+    section
+}
+
+/// The text of a string, number or boolean, as it would be written in a
+/// lemonrc.
+fn scalar<'a>(span: &'a str, value: DeValue<'a>) -> Result<Cow<'a, str>, ParseError<'a>> {
+    match value {
+        DeValue::String(s) => Ok(s),
+        DeValue::Integer(_) | DeValue::Float(_) | DeValue::Boolean(_) => Ok(span.into()),
+        v => Err((
+            span,
+            format!(
+                "Expected a string, number or boolean, found {}",
+                v.type_str()
+            ),
+        )
+            .into()),
+    }
+}
+
+/// Rewrites a lemonrc as a TOML config with the same options.
+pub fn convert(config: &str) -> String {
+    let mut toml = String::new();
+    let (global, sections) = super::lemonrc_parts(config);
+    // Repeated global options, like fonts, become arrays.
+    let mut global_options = Vec::<(&str, Vec<&str>)>::new();
+    let (variables, global) = global
+        .split('\n')
+        .partition::<Vec<_>, _>(|l| l.trim_start().starts_with("set "));
+    let global = global.join("\n");
+    for (key, value) in options_of(&global, |v| v.trim_matches('\'')) {
+        match global_options.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, values)) => values.push(value),
+            None => global_options.push((key, vec![value])),
+        }
+    }
+    if !global_options.is_empty() {
+        toml.push_str("[global]\n");
+        for (k, values) in global_options {
+            let values = values.into_iter().map(value).collect::<Vec<_>>();
+            match values.as_slice() {
+                [value] => toml.push_str(&format!("{} = {}\n", key(k), value)),
+                values => toml.push_str(&format!("{} = [{}]\n", key(k), values.join(", "))),
+            }
+        }
+    }
+    let variables = variables
+        .into_iter()
+        .filter_map(|l| l.trim_start()["set ".len()..].split_once('='))
+        .collect::<Vec<_>>();
+    if !variables.is_empty() {
+        if !toml.is_empty() {
+            toml.push('\n');
+        }
+        toml.push_str("[global.set]\n");
+        for (name, v) in variables {
+            let v = v.trim().trim_matches('\'');
+            toml.push_str(&format!("{} = {}\n", key(name.trim()), value(v)));
+        }
+    }
+    // Styles come first, as blocks can't be split by other tables.
+    let (styles, blocks) = sections
+        .map(|s| s.split_once('\n').unwrap_or((s, "")))
+        .partition::<Vec<_>, _>(|(header, _)| header.trim().starts_with("style:"));
+    for (header, options) in styles.into_iter().chain(blocks) {
+        if !toml.is_empty() {
+            toml.push('\n');
+        }
+        match header.trim().strip_prefix("style:") {
+            Some(name) => toml.push_str(&format!("[style.{}]\n", key(name.trim()))),
+            None => toml.push_str("[[block]]\n"),
+        }
+        let included = header
+            .trim_start()
+            .starts_with("include:")
+            .then_some(header);
+        for (k, v) in options_of(included.unwrap_or_default(), |v| v.trim_end_matches('\''))
+            .chain(options_of(options, |v| v.trim_end_matches('\'')))
+        {
+            toml.push_str(&format!("{} = {}\n", key(k), value(v)));
+        }
+    }
+    toml
+}
+
+/// The `key: value` lines of a lemonrc section, with `trim` applied to the
+/// values like the lemonrc parser does.
+fn options_of<'a>(
+    section: &'a str,
+    trim: impl Fn(&'a str) -> &'a str + 'a,
+) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+    section
+        .split('\n')
+        .filter(|s| !s.trim().is_empty())
+        .filter_map(|opt| super::split_option(opt).ok())
+        .map(move |(key, value)| (key, trim(value)))
+}
+
+/// A lemonrc key as a TOML key, only quoted if it has to be.
+fn key(k: &str) -> String {
+    if !k.is_empty()
+        && k.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        k.to_string()
+    } else {
+        value(k)
+    }
+}
+
+/// A lemonrc value as a TOML value, keeping booleans and numbers bare. Only
+/// numbers TOML reads back the same are kept bare, not `05` or ones that
+/// are too big.
+fn value(v: &str) -> String {
+    let integer = v.bytes().all(|b| b.is_ascii_digit())
+        && v.parse::<i64>().is_ok()
+        && (v == "0" || !v.starts_with('0'));
+    if v == "true" || v == "false" || integer {
+        return v.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in v.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parse, Alignment, Content};
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn converted_lemonrc_parses() {
+        let lemonrc = "\
+bottom: true
+>
+alignment: left
+interval: 05
+static: 007
+>
+alignment: right
+static: 99999999999999999999
+";
+        let toml = convert(lemonrc);
+        let (global_config, config) =
+            parse(&toml, Path::new("lemonrc.toml")).unwrap_or_else(|e| panic!("{}\n{}", toml, e));
+        assert!(global_config.bottom);
+        let left = &config[&Alignment::Left][0];
+        assert_eq!(left.interval, Duration::from_secs(5));
+        assert!(matches!(&left.content, Content::Static(s) if s == "007"));
+        let right = &config[&Alignment::Right][0];
+        assert!(matches!(&right.content, Content::Static(s) if s == "99999999999999999999"));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{env, path::PathBuf};
+
+const USAGE: &str = "\
+Usage: lemonjuice [OPTIONS] [COMMAND]
+
+Commands:
+    convert              Print the config, which must be a lemonrc, as TOML
+    msg <request>        Send <request> to the running bar and print its reply:
+                           refresh <block>, set <block> <text>, hide <block|bar>,
+                           show <block|bar>, reload, quit, list or error <block>
+                         Blocks are given by name or as <alignment>:<index>
+
+Options:
+    -c, --config <path>  Use the config file at <path>
+    -b, --bar <name>     Use the bar configured in $XDG_CONFIG_HOME/lemonjuice/<name>
+        --check          Check the config file for errors and exit
+    -h, --help           Print this message and exit";
+
+/// What the program was asked to do.
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum Command {
+    /// Show the bar.
+    #[default]
+    Run,
+    /// Rewrite a lemonrc config as TOML.
+    Convert,
+    /// Send a request to the running bar.
+    Msg,
+}
+
+/// Command line arguments.
+#[derive(Default)]
+pub struct Args {
+    pub command: Command,
+    pub config: Option<PathBuf>,
+    pub bar: Option<String>,
+    pub check: bool,
+    /// The request to send for `msg`.
+    pub message: Vec<String>,
+}
+
+impl Args {
+    /// Parses the process' arguments, exiting with the usage if they're
+    /// invalid or help was asked for.
+    pub fn from_env() -> Self {
+        match Self::parse(env::args().skip(1)) {
+            Ok(args) => args,
+            Err(None) => {
+                println!("{}", USAGE);
+                std::process::exit(0)
+            }
+            Err(Some(e)) => {
+                eprintln!("{}\n\n{}", e, USAGE);
+                std::process::exit(2)
+            }
+        }
+    }
+
+    /// Returns `Err(None)` if help was asked for.
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, Option<String>> {
+        let mut parsed = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| Some(format!("Missing value for '{}'", name)))
+            };
+            match arg.as_str() {
+                "-c" | "--config" => parsed.config = Some(value(&arg)?.into()),
+                "-b" | "--bar" => parsed.bar = Some(value(&arg)?),
+                "--check" => parsed.check = true,
+                "-h" | "--help" => return Err(None),
+                "convert" if parsed.command == Command::Run => parsed.command = Command::Convert,
+                // Everything after `msg` is the request, even if it looks
+                // like an option.
+                "msg" if parsed.command == Command::Run => {
+                    parsed.command = Command::Msg;
+                    parsed.message = args.by_ref().collect();
+                    if parsed.message.is_empty() {
+                        return Err(Some("Missing request for 'msg'".into()));
+                    }
+                }
+                _ => return Err(Some(format!("Unrecognised argument '{}'", arg))),
+            }
+        }
+        if parsed.config.is_some() && parsed.bar.is_some() {
+            return Err(Some("'--config' and '--bar' can't be used together".into()));
+        }
+        Ok(parsed)
+    }
+
+    /// The config file to use: the one given with `--config`, or the bar's
+    /// file (`config` by default) in `$XDG_CONFIG_HOME/lemonjuice`.
+    pub fn config_path(&self) -> Result<PathBuf, &'static str> {
+        if let Some(path) = &self.config {
+            return Ok(path.clone());
+        }
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+            .ok_or("Neither $XDG_CONFIG_HOME nor $HOME are set, use '--config'")?;
+        Ok(config_home
+            .join("lemonjuice")
+            .join(self.bar.as_deref().unwrap_or("config")))
+    }
+}
@@ -0,0 +1,36 @@
+use crate::{
+    block::Update,
+    ipc::{Reply, Request},
+};
+use async_std::sync::Sender;
+
+/// Everything the main loop reacts to.
+pub enum Event {
+    /// A block produced new output.
+    Update(Update),
+    /// One of the bar's windows needs to be redrawn.
+    Expose,
+    /// A mouse button was pressed at `x` over one of the bar's windows.
+    Click { window: u32, x: i16, button: u8 },
+    /// Monitors were plugged, unplugged or rearranged.
+    OutputsChanged,
+    /// Another client sent the bar a message, e.g. to dock a tray icon.
+    ClientMessage { message_type: u32, data: [u32; 5] },
+    /// A window the bar was watching was destroyed.
+    WindowGone(u32),
+    /// A window the bar was watching got a new `parent`.
+    Reparented { window: u32, parent: u32 },
+    /// Another client took over a selection owned by the bar.
+    SelectionLost(u32),
+    /// The config file changed, or the bar was asked to reload it.
+    Reload,
+    /// The bar was asked to exit.
+    Quit,
+    /// A client sent a request over the socket, to be answered on `reply`.
+    Message {
+        request: Request,
+        reply: Sender<Reply>,
+    },
+    /// `SIGRTMIN` plus this offset was received.
+    Signal(u8),
+}
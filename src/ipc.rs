@@ -0,0 +1,183 @@
+use crate::event::Event;
+use async_std::{
+    sync::{channel, Sender},
+    task,
+};
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::Shutdown,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+};
+
+/// What a client asked the bar to do. Blocks are given by name or as
+/// `<alignment>:<index>`, e.g. `left:0`.
+pub enum Request {
+    /// Rerun a block's command now.
+    Refresh(String),
+    /// Show this text in a block until its next update.
+    Set(String, String),
+    Hide(Target),
+    Show(Target),
+    Reload,
+    Quit,
+    /// Describe every block.
+    List,
+    /// Tell why a block's command failed.
+    Error(String),
+}
+
+/// What to hide or show.
+pub enum Target {
+    Bar,
+    Block(String),
+}
+
+impl FromStr for Request {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (command, args) = s.split_once(' ').unwrap_or((s, ""));
+        let args = args.trim_start();
+        let block = || match args {
+            "" => Err(format!("Missing block for '{}'", command)),
+            block => Ok(block.to_string()),
+        };
+        let target = || match args {
+            "bar" => Ok(Target::Bar),
+            _ => block().map(Target::Block),
+        };
+        let none = |request| match args {
+            "" => Ok(request),
+            _ => Err(format!("'{}' takes no arguments", command)),
+        };
+        match command {
+            "refresh" => Ok(Self::Refresh(block()?)),
+            "set" => {
+                let (block, text) = args.split_once(' ').unwrap_or((args, ""));
+                match block {
+                    "" => Err("Missing block for 'set'".into()),
+                    block => Ok(Self::Set(block.into(), text.into())),
+                }
+            }
+            "hide" => Ok(Self::Hide(target()?)),
+            "show" => Ok(Self::Show(target()?)),
+            "reload" => none(Self::Reload),
+            "quit" => none(Self::Quit),
+            "list" => none(Self::List),
+            "error" => Ok(Self::Error(block()?)),
+            _ => Err(format!(
+                "Unrecognised command '{}', expected refresh, set, hide, show, reload, quit, list or error",
+                command
+            )),
+        }
+    }
+}
+
+/// The reply to a request: its output, or why it failed.
+pub type Reply = Result<String, String>;
+
+/// Where the bar using the config at `config` listens:
+/// `$XDG_RUNTIME_DIR/lemonjuice/<config file name>.sock`.
+pub fn socket_path(config: &Path) -> Result<PathBuf, &'static str> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+        .filter(|d| !d.is_empty())
+        .ok_or("$XDG_RUNTIME_DIR isn't set")?;
+    let name = config
+        .file_name()
+        .ok_or("The config path has no file name")?;
+    let mut socket = PathBuf::from(runtime_dir).join("lemonjuice").join(name);
+    socket.set_extension("sock");
+    Ok(socket)
+}
+
+/// The listening socket, removed when this is dropped.
+pub struct Socket(PathBuf);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Listens for requests on a socket at `path`, one per line, sending each
+/// to `sender` and writing back its reply: the lines of its output, then
+/// `ok` or `error: <reason>`.
+pub fn listen(path: &Path, sender: Sender<Event>) -> io::Result<Socket> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // A socket nobody answers on was left behind by a bar that crashed.
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another bar is listening on '{}'", path.display()),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+            thread::spawn(move || serve(stream, &sender));
+        }
+    });
+    Ok(Socket(path.to_path_buf()))
+}
+
+/// Answers the requests of one client until it disconnects.
+fn serve(stream: UnixStream, sender: &Sender<Event>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match line.parse() {
+            Ok(request) => task::block_on(async {
+                let (reply, replied) = channel(1);
+                sender.send(Event::Message { request, reply }).await;
+                replied
+                    .recv()
+                    .await
+                    .unwrap_or_else(|| Err("The bar is exiting".into()))
+            }),
+            Err(e) => Err(e),
+        };
+        match reply {
+            Ok(output) if output.is_empty() => writeln!(writer, "ok")?,
+            Ok(output) => writeln!(writer, "{}\nok", output.trim_end_matches('\n'))?,
+            Err(e) => writeln!(writer, "error: {}", e)?,
+        }
+    }
+    Ok(())
+}
+
+/// Sends `request` to the bar listening at `path`, returning its reply.
+pub fn send(path: &Path, request: &str) -> Reply {
+    let exchange = || -> io::Result<String> {
+        let mut stream = UnixStream::connect(path)?;
+        writeln!(stream, "{}", request)?;
+        stream.shutdown(Shutdown::Write)?;
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply)?;
+        Ok(reply)
+    };
+    let reply =
+        exchange().map_err(|e| format!("Couldn't reach the bar at '{}': {}", path.display(), e))?;
+    let reply = reply.trim_end_matches('\n');
+    let (output, status) = match reply.rsplit_once('\n') {
+        Some((output, status)) => (format!("{}\n", output), status),
+        None => (String::new(), reply),
+    };
+    match status.strip_prefix("error: ") {
+        Some(e) => Err(e.to_string()),
+        None if status == "ok" => Ok(output),
+        None => Err(format!("Unexpected reply '{}'", status)),
+    }
+}
@@ -1,34 +1,278 @@
 mod bar;
 mod block;
+mod cli;
+mod event;
+mod ipc;
+mod signal;
 mod text;
+mod watch;
+
+use async_std::{
+    sync::{channel, Sender},
+    task,
+};
+use bar::Bar;
+use block::{Alignment, Config, GlobalConfig, Tasks};
+use event::Event;
+use ipc::{Reply, Request, Target};
+use std::path::Path;
+
+/// Reads and parses the config file at `path`.
+fn load(path: &Path, args: &cli::Args) -> Result<(GlobalConfig, Config), String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Couldn't read config file '{}': {}", path.display(), e))?;
+    let (mut global_config, config) = block::parse(&source, path).map_err(|e| e.to_string())?;
+    if global_config.name.is_none() {
+        global_config.name = args.bar.clone();
+    }
+    Ok((global_config, config))
+}
+
+/// Starts forwarding the signals the blocks in `config` update on.
+fn handle_signals(config: &Config) {
+    for offset in config.values().flatten().filter_map(|b| b.signal()) {
+        if let Err(e) = signal::handle(offset) {
+            eprintln!("Failed to listen for SIGRTMIN+{}: {}", offset, e);
+        }
+    }
+}
+
+/// Switches `bar` to the config at `path`, keeping the old one and showing
+/// the error if it's invalid.
+fn reload(
+    path: &Path,
+    args: &cli::Args,
+    bar: &mut Bar,
+    tasks: &mut Tasks,
+    sender: &Sender<Event>,
+) -> Result<(), String> {
+    match load(path, args) {
+        Ok((global_config, config)) => {
+            let old = bar.reload(global_config, config);
+            let kept = std::mem::take(tasks);
+            *tasks = block::respawn_blocks(kept, old, &mut bar.contents, sender.clone());
+            handle_signals(&bar.contents);
+            bar.render_contents();
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            // Only the first line fits in the bar.
+            bar.show_error(e.lines().next().unwrap_or_default().to_string());
+            Err(e)
+        }
+    }
+}
+
+/// Carries out a request sent over the socket, except for `quit`.
+async fn answer(
+    request: Request,
+    (path, args): (&Path, &cli::Args),
+    bar: &mut Bar,
+    tasks: &mut Tasks,
+    sender: &Sender<Event>,
+) -> Reply {
+    match request {
+        Request::Refresh(id) => {
+            let (alignment, index) = block::find(&bar.contents, &id)?;
+            if !tasks.refresh(alignment, index).await {
+                let block = &bar.contents[&alignment][index];
+                return Err(format!("'{}' isn't a command block", block.name()));
+            }
+        }
+        Request::Set(id, text) => {
+            let (alignment, index) = block::find(&bar.contents, &id)?;
+            if bar.set_output(alignment, index, &text) {
+                bar.render_contents();
+            }
+        }
+        Request::Hide(target) => set_hidden(bar, target, true)?,
+        Request::Show(target) => set_hidden(bar, target, false)?,
+        Request::Reload => reload(path, args, bar, tasks, sender)
+            .map_err(|e| e.lines().next().unwrap_or_default().to_string())?,
+        Request::Quit => (),
+        Request::List => {
+            let mut list = String::new();
+            for alignment in [Alignment::Left, Alignment::Middle, Alignment::Right] {
+                for (index, block) in bar
+                    .contents
+                    .get(&alignment)
+                    .into_iter()
+                    .flatten()
+                    .enumerate()
+                {
+                    let output = block.output(0).unwrap_or_default();
+                    let position = format!("{}:{}", alignment, index);
+                    list.push_str(&format!("{}\t{}\t{}\n", block.name(), position, output));
+                }
+            }
+            return Ok(list);
+        }
+        Request::Error(id) => {
+            let (alignment, index) = block::find(&bar.contents, &id)?;
+            let block = &bar.contents[&alignment][index];
+            let errors = block
+                .errors()
+                .into_iter()
+                .map(|(monitor, failure)| format!("monitor {}: {}\n", monitor, failure.message))
+                .collect();
+            return Ok(errors);
+        }
+    }
+    Ok(String::new())
+}
+
+/// Hides or shows the whole bar or one of its blocks.
+fn set_hidden(bar: &mut Bar, target: Target, hidden: bool) -> Result<(), String> {
+    match target {
+        Target::Bar => bar.set_hidden(hidden),
+        Target::Block(id) => {
+            let (alignment, index) = block::find(&bar.contents, &id)?;
+            let blocks = bar
+                .contents
+                .get_mut(&alignment)
+                .expect("the block was just found");
+            if blocks[index].set_hidden(hidden) {
+                bar.render_contents();
+            }
+        }
+    }
+    Ok(())
+}
 
 fn main() {
-    let (global_config, config) = match block::parse(
-        &std::fs::read_to_string("/home/mendess/.config/lemonbar/lemonrc").unwrap(),
-        0,
-    ) {
+    let args = cli::Args::from_env();
+    let path = match args.config_path() {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    };
+    // Sending a request doesn't need the config, which could run commands.
+    if args.command == cli::Command::Msg {
+        let reply = ipc::socket_path(&path)
+            .map_err(String::from)
+            .and_then(|socket| ipc::send(&socket, &args.message.join(" ")));
+        match reply {
+            Ok(output) => print!("{}", output),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+    let (global_config, config) = match load(&path, &args) {
         Ok(v) => v,
         Err(e) => {
             eprintln!("{}", e);
             std::process::exit(1)
         }
     };
-    let mut bar = bar::Bar::new(global_config, config).unwrap();
-    bar.contents.values_mut().flatten().for_each(|c| {
-        c.update();
+    if args.check {
+        println!("{}: ok", path.display());
+        return;
+    }
+    if args.command == cli::Command::Convert {
+        if block::is_toml(&path) {
+            eprintln!("{} is already a TOML config", path.display());
+            std::process::exit(1)
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(source) => print!("{}", block::convert(&source)),
+            Err(e) => {
+                eprintln!("Couldn't read config file '{}': {}", path.display(), e);
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+    let mut bar = match Bar::new(global_config, config) {
+        Ok(bar) => bar,
+        Err(e) => {
+            eprintln!("Couldn't create the bar: {}", e);
+            std::process::exit(1)
+        }
+    };
+    bar.render_contents();
+    // The sender is kept alive here so the loop only ends when the bar is killed,
+    // even if there are no blocks that produce updates.
+    let (sender, receiver) = channel(64);
+    let mut tasks = block::spawn_blocks(&bar.contents, sender.clone());
+    if let Err(e) = signal::listen(sender.clone()) {
+        eprintln!("Failed to listen for signals: {}", e);
+    }
+    handle_signals(&bar.contents);
+    if let Err(e) = watch::watch(&path, sender.clone()) {
+        eprintln!("Warning: not reloading when the config changes: {}", e);
+    }
+    // Removes the socket when the bar exits.
+    let _socket = match ipc::socket_path(&path) {
+        Ok(socket) => ipc::listen(&socket, sender.clone()).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+    .map_err(|e| eprintln!("Warning: not listening for requests: {}", e));
+    bar.listen(sender.clone());
+    task::block_on(async {
+        while let Some(event) = receiver.recv().await {
+            match event {
+                Event::Update(update) => {
+                    if bar.apply(update, &tasks) {
+                        bar.render_contents();
+                    }
+                }
+                Event::Expose => bar.render_contents(),
+                Event::Click { window, x, button } => bar.click(window, x, button),
+                Event::Signal(offset) => {
+                    for (alignment, index) in bar.signalled_by(offset) {
+                        tasks.refresh(alignment, index).await;
+                    }
+                }
+                Event::OutputsChanged => match bar.update_outputs() {
+                    Ok(true) => {
+                        // Multi monitor blocks need to run once per monitor now.
+                        tasks = block::spawn_blocks(&bar.contents, sender.clone());
+                        bar.render_contents();
+                    }
+                    Ok(false) => (),
+                    Err(e) => eprintln!("Failed to recreate the bars: {}", e),
+                },
+                Event::ClientMessage { message_type, data } => {
+                    if bar.client_message(message_type, data) {
+                        bar.render_contents();
+                    }
+                }
+                Event::WindowGone(window) => {
+                    if bar.window_gone(window) {
+                        bar.render_contents();
+                    }
+                }
+                Event::Reparented { window, parent } => {
+                    if bar.reparented(window, parent) {
+                        bar.render_contents();
+                    }
+                }
+                Event::SelectionLost(selection) => {
+                    if bar.selection_lost(selection) {
+                        bar.render_contents();
+                    }
+                }
+                // The old config keeps running until the error is fixed.
+                Event::Reload => {
+                    let _ = reload(&path, &args, &mut bar, &mut tasks, &sender);
+                }
+                Event::Quit => break,
+                Event::Message { request, reply } => {
+                    let quit = matches!(request, Request::Quit);
+                    let answer =
+                        answer(request, (&path, &args), &mut bar, &mut tasks, &sender).await;
+                    reply.send(answer).await;
+                    if quit {
+                        break;
+                    }
+                }
+            }
+        }
     });
-    bar.render_contents(0);
-    // bar.render(Text {
-    //     attr: Attributes::default()
-    //         .with_padding(Padding::left(1000.0).with_right(5000.0))
-    //         .with_bg_color(Some("#00FF00".parse().unwrap())),
-    //     text: "Test string don't upvote".into(),
-    // });
-    // std::thread::sleep(std::time::Duration::from_secs(3));
-    // println!("Done");
-    // bar.render(Text {
-    //     attr: Default::default(),
-    //     text: "What happened!".into(),
-    // });
-    std::thread::sleep(std::time::Duration::from_secs(10));
 }
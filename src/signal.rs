@@ -0,0 +1,75 @@
+use crate::event::Event;
+use async_std::{sync::Sender, task};
+use std::{
+    fs::File,
+    io::{self, Read},
+    os::unix::io::FromRawFd,
+    sync::atomic::{AtomicI32, Ordering},
+    thread,
+};
+
+/// Write end of the pipe the signal handler reports signals through.
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handler(signal: libc::c_int) {
+    let signal = signal as u8;
+    unsafe {
+        libc::write(
+            PIPE.load(Ordering::Relaxed),
+            &signal as *const u8 as *const libc::c_void,
+            1,
+        );
+    }
+}
+
+/// Forwards `SIGTERM` and `SIGINT` to `sender` as `Event::Quit`, `SIGHUP` as
+/// `Event::Reload` and, once [`handle`] was called for them, `SIGRTMIN+n` as
+/// `Event::Signal(n)`.
+///
+/// The handler only writes the signal number to a pipe, which is read on a
+/// background thread, so no work happens in signal context.
+pub fn listen(sender: Sender<Event>) -> io::Result<()> {
+    let mut fds = [0; 2];
+    // Commands the bar runs mustn't inherit the pipe.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    PIPE.store(fds[1], Ordering::Relaxed);
+    for &signal in &[libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        install(signal)?;
+    }
+    let mut pipe = unsafe { File::from_raw_fd(fds[0]) };
+    thread::spawn(move || {
+        let mut signal = [0];
+        while pipe.read_exact(&mut signal).is_ok() {
+            let event = match i32::from(signal[0]) {
+                libc::SIGTERM | libc::SIGINT => Event::Quit,
+                libc::SIGHUP => Event::Reload,
+                signal => Event::Signal((signal - libc::SIGRTMIN()) as u8),
+            };
+            task::block_on(sender.send(event));
+        }
+    });
+    Ok(())
+}
+
+/// Starts forwarding `SIGRTMIN` plus `offset`. Handling a signal twice is
+/// harmless.
+pub fn handle(offset: u8) -> io::Result<()> {
+    install(libc::SIGRTMIN() + i32::from(offset))
+}
+
+fn install(signal: libc::c_int) -> io::Result<()> {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whether `offset` can be added to `SIGRTMIN` without going past `SIGRTMAX`.
+pub fn is_valid_offset(offset: u8) -> bool {
+    libc::SIGRTMIN() + i32::from(offset) <= libc::SIGRTMAX()
+}
@@ -1,10 +1,9 @@
 pub mod color;
+pub mod markup;
 
 use cairo::{Context, Surface};
 use color::Color;
 use pango::{EllipsizeMode, FontDescription};
-use pangocairo;
-use paste;
 use std::fmt;
 
 macro_rules! padding_builder {
@@ -71,6 +70,30 @@ pub struct Attributes {
     pub fg_color: Color,
     pub bg_color: Option<Color>,
     pub padding: Padding,
+    /// Colour of the under and overline, defaults to `fg_color`.
+    pub line_color: Option<Color>,
+    pub line_width: f64,
+    pub underline: bool,
+    pub overline: bool,
+    /// Space the text takes up at least, with the text placed in it
+    /// according to `align`.
+    pub min_width: Option<MinWidth>,
+    pub align: Align,
+}
+
+/// A width given in pixels, or as the width of another text.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MinWidth {
+    Pixels(f64),
+    Text(String),
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
 }
 
 impl Attributes {
@@ -96,6 +119,14 @@ impl Attributes {
     pub fn with_font(self, font: Font) -> Self {
         Self { font, ..self }
     }
+
+    #[allow(dead_code)]
+    pub fn with_line_color<C: Into<Option<Color>>>(self, line_color: C) -> Self {
+        Self {
+            line_color: line_color.into(),
+            ..self
+        }
+    }
 }
 
 impl Default for Attributes {
@@ -105,6 +136,12 @@ impl Default for Attributes {
             fg_color: Color::white(),
             bg_color: None,
             padding: Padding::default(),
+            line_color: None,
+            line_width: 1.0,
+            underline: false,
+            overline: false,
+            min_width: None,
+            align: Align::Left,
         }
     }
 }
@@ -119,22 +156,77 @@ fn show_pango_layout(cairo_context: &cairo::Context, layout: &pango::Layout) {
     pangocairo::functions::show_layout(cairo_context, layout);
 }
 
+fn set_layout_text(layout: &pango::Layout, text: &str, markup: bool) {
+    if markup {
+        layout.set_markup(text);
+    } else {
+        layout.set_text(text);
+    }
+}
+
+/// Returns `text` if it's valid Pango markup, otherwise warns and escapes it
+/// so it's shown as plain text.
+pub fn check_markup(text: String) -> String {
+    match pango::parse_markup(&text, '\0') {
+        Ok(_) => text,
+        Err(e) => {
+            eprintln!("Warning: invalid markup '{}': {}", text, e);
+            let mut escaped = String::with_capacity(text.len());
+            for c in text.chars() {
+                match c {
+                    '&' => escaped.push_str("&amp;"),
+                    '<' => escaped.push_str("&lt;"),
+                    '>' => escaped.push_str("&gt;"),
+                    '\'' => escaped.push_str("&#39;"),
+                    '"' => escaped.push_str("&quot;"),
+                    c => escaped.push(c),
+                }
+            }
+            escaped
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Text {
     pub attr: Attributes,
     pub text: String,
+    /// Commands to run when this text is clicked, indexed by X mouse button
+    /// minus one. They take precedence over the block's own actions.
+    pub actions: [Option<String>; 5],
+    /// Whether `text` is Pango markup instead of plain text.
+    pub markup: bool,
 }
 
 impl Text {
-    pub(crate) fn compute(self, surface: &Surface) -> Result<ComputedText, String> {
+    pub(crate) fn compute(mut self, surface: &Surface) -> Result<ComputedText, String> {
         let (width, height) = {
-            let context = Context::new(&surface);
+            let context = Context::new(surface);
             let layout = create_pango_layout(&context)?;
-            layout.set_text(&self.text);
+            let min_width = match &self.attr.min_width {
+                Some(MinWidth::Pixels(width)) => *width,
+                Some(MinWidth::Text(text)) => {
+                    set_layout_text(&layout, text, self.markup);
+                    layout.set_font_description(Some(&self.attr.font.0));
+                    f64::from(layout.get_pixel_size().0)
+                }
+                None => 0.0,
+            };
+            set_layout_text(&layout, &self.text, self.markup);
             layout.set_font_description(Some(&self.attr.font.0));
 
-            let padding = &self.attr.padding;
             let (text_width, text_height) = layout.get_pixel_size();
+            // The space missing to reach the minimum width becomes padding.
+            let missing = (min_width - f64::from(text_width)).max(0.0);
+            let padding = &mut self.attr.padding;
+            match self.attr.align {
+                Align::Left => padding.right += missing,
+                Align::Center => {
+                    padding.left += missing / 2.0;
+                    padding.right += missing / 2.0;
+                }
+                Align::Right => padding.left += missing,
+            }
             let width = f64::from(text_width) + padding.left + padding.right;
             let height = f64::from(text_height) + padding.top + padding.bottom;
             (width, height)
@@ -143,6 +235,8 @@ impl Text {
         Ok(ComputedText {
             attr: self.attr,
             text: self.text,
+            actions: self.actions,
+            markup: self.markup,
             x: 0.0,
             y: 0.0,
             width,
@@ -155,7 +249,10 @@ impl Text {
 // having to call the (relatively) expensive .compute().
 impl PartialEq<ComputedText> for Text {
     fn eq(&self, other: &ComputedText) -> bool {
-        self.attr == other.attr && self.text == other.text
+        self.attr == other.attr
+            && self.text == other.text
+            && self.actions == other.actions
+            && self.markup == other.markup
     }
 }
 
@@ -163,6 +260,8 @@ impl PartialEq<ComputedText> for Text {
 pub struct ComputedText {
     pub attr: Attributes,
     pub text: String,
+    pub actions: [Option<String>; 5],
+    pub markup: bool,
 
     pub x: f64,
     pub y: f64,
@@ -172,32 +271,48 @@ pub struct ComputedText {
 
 impl ComputedText {
     pub fn render(&self, surface: &Surface) -> Result<(), String> {
-        let context = Context::new(&surface);
+        let context = Context::new(surface);
         let layout = create_pango_layout(&context)?;
-        layout.set_text(&self.text);
+        set_layout_text(&layout, &self.text, self.markup);
         layout.set_font_description(Some(&self.attr.font.0));
 
         context.translate(self.x, self.y);
 
         // Set the width/height on the Pango layout so that it word-wraps/ellipises.
         let padding = &self.attr.padding;
-        let text_width = self.width - padding.left - padding.right;
+        let text_width = (self.width - padding.left - padding.right).max(0.0);
         let text_height = self.height - padding.top - padding.bottom;
         layout.set_ellipsize(EllipsizeMode::End);
         layout.set_width(text_width as i32 * pango::SCALE);
         layout.set_height(text_height as i32 * pango::SCALE);
 
-        let bg_color = &self.attr.bg_color.clone().unwrap_or_else(Color::black);
+        let bg_color = &self.attr.bg_color.unwrap_or_else(Color::black);
         bg_color.apply_to_context(&context);
-        // FIXME: The use of `height` isnt' right here: we want to do the
-        // full height of the bar, not the full height of the text. It
-        // would be useful if we could do Surface.get_height(), but that
-        // doesn't seem to be available in cairo-rs for some reason?
+        // `height` is the full height of the bar, the text is centered in it.
         context.rectangle(0.0, 0.0, self.width, self.height);
+        // Truncated texts must not spill over their neighbours.
+        context.clip_preserve();
         context.fill();
 
+        let line_width = self.attr.line_width;
+        if self.attr.underline || self.attr.overline {
+            self.attr
+                .line_color
+                .unwrap_or(self.attr.fg_color)
+                .apply_to_context(&context);
+            if self.attr.overline {
+                context.rectangle(0.0, 0.0, self.width, line_width);
+            }
+            if self.attr.underline {
+                context.rectangle(0.0, self.height - line_width, self.width, line_width);
+            }
+            context.fill();
+        }
+
         self.attr.fg_color.apply_to_context(&context);
-        context.translate(padding.left, padding.top);
+        let (_, pixel_height) = layout.get_pixel_size();
+        let y = ((text_height - f64::from(pixel_height)) / 2.0).max(0.0);
+        context.translate(padding.left, padding.top + y);
         show_pango_layout(&context, &layout);
 
         Ok(())
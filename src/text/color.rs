@@ -28,6 +28,12 @@ impl Color {
     pub fn apply_to_context(&self, cr: &Context) {
         cr.set_source_rgb(self.1, self.2, self.3);
     }
+
+    /// The X pixel value of this color on a 24 bit TrueColor visual.
+    pub fn to_pixel(self) -> u32 {
+        let channel = |c: f64| (c * 255.0).round() as u32;
+        channel(self.1) << 16 | channel(self.2) << 8 | channel(self.3)
+    }
 }
 
 impl FromStr for Color {
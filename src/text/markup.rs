@@ -0,0 +1,216 @@
+use super::{color::Color, Attributes, Font, Padding, Text};
+use std::{iter::Peekable, str::Chars};
+
+/// Parses lemonbar's `%{...}` formatting tags in `input` into a sequence of
+/// spans, styled on top of `base`.
+///
+/// The supported tags are `F`, `B` and `U` (colours, `-` resets them), `T`
+/// (font index into `fonts`, starting at 1), `+u`/`-u`/`!u` and
+/// `+o`/`-o`/`!o` (lines), `O` (offset in pixels), `R` (swap colours) and
+/// `A<button>:cmd:` ... `A` (clickable regions). Unknown tags are ignored.
+pub fn parse(input: &str, base: &Attributes, fonts: &[Font]) -> Vec<Text> {
+    let mut parser = Parser {
+        base,
+        fonts,
+        attr: base.clone(),
+        actions: vec![],
+        text: String::new(),
+        spans: vec![],
+    };
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '%' && chars.peek() == Some(&'{') {
+            chars.next();
+            parser.flush();
+            parser.tag(&mut chars);
+        } else {
+            parser.text.push(c);
+        }
+    }
+    parser.flush();
+    parser.spans
+}
+
+struct Parser<'a> {
+    base: &'a Attributes,
+    fonts: &'a [Font],
+    attr: Attributes,
+    /// Open `%{A}` tags, innermost last.
+    actions: Vec<(usize, String)>,
+    text: String,
+    spans: Vec<Text>,
+}
+
+impl Parser<'_> {
+    fn flush(&mut self) {
+        if !self.text.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            self.push(text, self.attr.clone());
+        }
+    }
+
+    fn push(&mut self, text: String, attr: Attributes) {
+        let mut actions: [Option<String>; 5] = Default::default();
+        for (button, action) in &self.actions {
+            actions[*button] = Some(action.clone());
+        }
+        self.spans.push(Text {
+            attr,
+            text,
+            actions,
+            markup: false,
+        });
+    }
+
+    /// Applies every command of a tag, up to its closing `}`.
+    fn tag(&mut self, chars: &mut Peekable<Chars<'_>>) {
+        while let Some(c) = chars.next() {
+            match c {
+                '}' => return,
+                ' ' => (),
+                'F' => {
+                    if let Some(c) = self.color(chars) {
+                        self.attr.fg_color = c.unwrap_or(self.base.fg_color);
+                    }
+                }
+                'B' => {
+                    if let Some(c) = self.color(chars) {
+                        self.attr.bg_color = c.or(self.base.bg_color);
+                    }
+                }
+                'U' => {
+                    if let Some(c) = self.color(chars) {
+                        self.attr.line_color = c.or(self.base.line_color);
+                    }
+                }
+                'T' => {
+                    let arg = argument(chars);
+                    self.attr.font = match arg.parse::<usize>() {
+                        Ok(i) if i > 0 && i <= self.fonts.len() => self.fonts[i - 1].clone(),
+                        _ => self.base.font.clone(),
+                    };
+                }
+                'O' => {
+                    if let Ok(offset) = argument(chars).parse() {
+                        let attr = self.attr.clone().with_padding(Padding::left(offset));
+                        self.push(String::new(), attr);
+                    }
+                }
+                'R' => {
+                    let bg = self.attr.bg_color.unwrap_or_else(Color::black);
+                    self.attr.bg_color = Some(self.attr.fg_color);
+                    self.attr.fg_color = bg;
+                }
+                '+' | '-' | '!' => {
+                    let line = match chars.next() {
+                        Some('u') => &mut self.attr.underline,
+                        Some('o') => &mut self.attr.overline,
+                        _ => continue,
+                    };
+                    *line = match c {
+                        '+' => true,
+                        '-' => false,
+                        _ => !*line,
+                    };
+                }
+                'A' => self.action(chars),
+                // Alignment and monitor tags don't make sense inside a block.
+                _ => {
+                    argument(chars);
+                }
+            }
+        }
+    }
+
+    /// Parses the argument of a colour command, returning `None` if it's
+    /// invalid and `Some(None)` if it resets the colour.
+    fn color(&self, chars: &mut Peekable<Chars<'_>>) -> Option<Option<Color>> {
+        match argument(chars).as_str() {
+            "-" => Some(None),
+            color => color.parse().ok().map(Some),
+        }
+    }
+
+    /// Opens a clickable region with `A<button>:cmd:` or closes the innermost
+    /// one with a bare `A`.
+    fn action(&mut self, chars: &mut Peekable<Chars<'_>>) {
+        let mut button = String::new();
+        while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            button.push(*c);
+            chars.next();
+        }
+        if chars.peek() != Some(&':') {
+            self.actions.pop();
+            return;
+        }
+        chars.next();
+        let mut cmd = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&':') => {
+                    chars.next();
+                    cmd.push(':');
+                }
+                ':' => break,
+                c => cmd.push(c),
+            }
+        }
+        let button = button.parse::<usize>().unwrap_or(1);
+        if (1..=5).contains(&button) {
+            self.actions.push((button - 1, cmd));
+        }
+    }
+}
+
+/// Consumes a command's argument, which ends at a space or the end of the tag.
+fn argument(chars: &mut Peekable<Chars<'_>>) -> String {
+    let mut arg = String::new();
+    while let Some(c) = chars.peek().filter(|&&c| c != ' ' && c != '}') {
+        arg.push(*c);
+        chars.next();
+    }
+    arg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(input: &str) -> Vec<Text> {
+        parse(input, &Attributes::default(), &[])
+    }
+
+    #[test]
+    fn action_with_escaped_colon() {
+        let spans = spans("%{A3:x\\:y:}a%{A}b");
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].text, "a");
+        assert_eq!(spans[0].actions[2].as_deref(), Some("x:y"));
+        assert_eq!(spans[1].text, "b");
+        assert!(spans[1].actions.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn color_reset() {
+        let spans = spans("%{F#ff0000}a%{F-}b");
+        assert_eq!(spans[0].attr.fg_color, Color::red());
+        assert_eq!(spans[1].attr.fg_color, Attributes::default().fg_color);
+    }
+
+    #[test]
+    fn reverse() {
+        let base = Attributes::default().with_bg_color(Color::blue());
+        let spans = parse("%{R}a", &base, &[]);
+        assert_eq!(spans[0].attr.fg_color, Color::blue());
+        assert_eq!(spans[0].attr.bg_color, Some(Color::white()));
+    }
+
+    #[test]
+    fn offset() {
+        let spans = spans("a%{O10}b");
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[1].text, "");
+        assert_eq!(spans[1].attr.padding.left, 10.0);
+        assert_eq!(spans[2].text, "b");
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{block, event::Event};
+use async_std::{sync::Sender, task};
+use std::{
+    collections::HashMap,
+    ffi::{CString, OsString},
+    fs::File,
+    io::{self, Read},
+    mem,
+    os::unix::{ffi::OsStrExt, io::FromRawFd},
+    path::{Path, PathBuf},
+    thread,
+};
+
+/// The names of the watched files, by the watch descriptor of their
+/// directory.
+type Watches = HashMap<i32, Vec<OsString>>;
+
+/// Sends `Event::Reload` to `sender` every time the config at `path`, or a
+/// file it includes, is written to or replaced. The included files are
+/// looked up again after every change, as includes may have been added or
+/// removed.
+///
+/// The files' directories are watched instead of the files themselves
+/// because most editors save by replacing the file, which would end a watch
+/// on it.
+pub fn watch(path: &Path, sender: Sender<Event>) -> io::Result<()> {
+    if path.file_name().is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file"));
+    }
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut inotify = unsafe { File::from_raw_fd(fd) };
+    let mut watches = add_watches(fd, &block::config_files(path), HashMap::new());
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while let Ok(len) = inotify.read(&mut buf) {
+            let mut changed = false;
+            let mut events = &buf[..len];
+            // Each event is a header followed by the NUL padded file name.
+            while events.len() >= mem::size_of::<libc::inotify_event>() {
+                let event =
+                    unsafe { (events.as_ptr() as *const libc::inotify_event).read_unaligned() };
+                let start = mem::size_of::<libc::inotify_event>();
+                let end = (start + event.len as usize).min(events.len());
+                let file = events[start..end].split(|&b| b == 0).next().unwrap_or(&[]);
+                changed |= watches
+                    .get(&event.wd)
+                    .is_some_and(|names| names.iter().any(|n| n.as_bytes() == file));
+                events = &events[end..];
+            }
+            if changed {
+                watches = add_watches(fd, &block::config_files(&path), watches);
+                task::block_on(sender.send(Event::Reload));
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Watches the directories of `paths`, and stops watching those of `old`
+/// that are no longer needed.
+fn add_watches(fd: i32, paths: &[PathBuf], old: Watches) -> Watches {
+    let mut watches = Watches::new();
+    for path in paths {
+        let name = match path.file_name() {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        let wd = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+            .and_then(|dir| {
+                // Watching a directory again returns the same descriptor.
+                match unsafe {
+                    libc::inotify_add_watch(
+                        fd,
+                        dir.as_ptr(),
+                        libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO,
+                    )
+                } {
+                    wd if wd < 0 => Err(io::Error::last_os_error()),
+                    wd => Ok(wd),
+                }
+            });
+        let wd = match wd {
+            Ok(wd) => wd,
+            Err(e) => {
+                eprintln!(
+                    "Warning: not reloading when '{}' changes: {}",
+                    path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        watches.entry(wd).or_default().push(name);
+    }
+    for wd in old.keys().filter(|wd| !watches.contains_key(wd)) {
+        unsafe { libc::inotify_rm_watch(fd, *wd) };
+    }
+    watches
+}